@@ -1,11 +1,20 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::env;
 use std::error::Error;
-use std::fs::{File, OpenOptions};
-use std::io;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
+use std::io::{self, IsTerminal};
 use std::mem;
-use std::os::unix::fs::FileExt;
+use std::ops::Range;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const NOT_EXIST: i32 = -1;
 
@@ -17,36 +26,164 @@ const PAGE_MAX_NUM: usize = 64;
 
 const NODE_KIND_SIZE: usize = size_of::<NodeKind>();
 const NODE_IS_ROOT_SIZE: usize = size_of::<bool>();
-const NODE_PARENT_SIZE: usize = size_of::<i32>();
 const NODE_N_CELLS_SIZE: usize = size_of::<u32>();
-const NODE_HEADER_SIZE: usize =
-    NODE_KIND_SIZE + NODE_IS_ROOT_SIZE + NODE_PARENT_SIZE + NODE_N_CELLS_SIZE;
+const NODE_HEADER_SIZE: usize = NODE_KIND_SIZE + NODE_IS_ROOT_SIZE + NODE_N_CELLS_SIZE;
+const NODE_KIND_OFFSET: usize = 0;
+const NODE_IS_ROOT_OFFSET: usize = NODE_KIND_OFFSET + NODE_KIND_SIZE;
+const NODE_N_CELLS_OFFSET: usize = NODE_IS_ROOT_OFFSET + NODE_IS_ROOT_SIZE;
+// format versions before 4 kept the parent page index where n_cells now
+// starts; splits find the parent by descending from the root instead
+const OLD_NODE_PARENT_SIZE: usize = size_of::<i32>();
 
 const LEAF_NODE_NEXT_LEAF_SIZE: usize = size_of::<i32>();
 const LEAF_NODE_HEADER_SIZE: usize = NODE_HEADER_SIZE + LEAF_NODE_NEXT_LEAF_SIZE;
+const LEAF_NODE_NEXT_LEAF_OFFSET: usize = NODE_HEADER_SIZE;
 const LEAF_NODE_SPACE_FOR_CELLS: usize = PAGE_SIZE - LEAF_NODE_HEADER_SIZE;
 const LEAF_NODE_CELL_KEY_SIZE: usize = size_of::<i64>();
 const LEAF_NODE_CELL_SIZE: usize =
     LEAF_NODE_CELL_KEY_SIZE + ID_SIZE + NAME_MAX_SIZE + DESCRIPTION_MAX_SIZE;
-const LEAF_NODE_CELL_MAX_NUM: usize = LEAF_NODE_SPACE_FOR_CELLS / LEAF_NODE_CELL_SIZE;
+// every cell is reached through a slot holding its position in the cell
+// area, so inserting in the middle of a leaf only shifts the slots
+const LEAF_NODE_SLOT_SIZE: usize = size_of::<u16>();
+const LEAF_NODE_CELL_MAX_NUM: usize =
+    LEAF_NODE_SPACE_FOR_CELLS / (LEAF_NODE_SLOT_SIZE + LEAF_NODE_CELL_SIZE);
+const LEAF_NODE_SLOTS_OFFSET: usize = LEAF_NODE_HEADER_SIZE;
+const LEAF_NODE_CELLS_OFFSET: usize =
+    LEAF_NODE_SLOTS_OFFSET + LEAF_NODE_CELL_MAX_NUM * LEAF_NODE_SLOT_SIZE;
+const LEAF_NODE_CELL_KEY_OFFSET: usize = 0;
+const LEAF_NODE_CELL_ID_OFFSET: usize = LEAF_NODE_CELL_KEY_OFFSET + LEAF_NODE_CELL_KEY_SIZE;
+const LEAF_NODE_CELL_NAME_OFFSET: usize = LEAF_NODE_CELL_ID_OFFSET + ID_SIZE;
+const LEAF_NODE_CELL_DESCRIPTION_OFFSET: usize = LEAF_NODE_CELL_NAME_OFFSET + NAME_MAX_SIZE;
 
 const INTERNAL_NODE_RIGHT_CHILD_SIZE: usize = size_of::<i32>();
 const INTERNAL_NODE_HEADER_SIZE: usize = NODE_HEADER_SIZE + INTERNAL_NODE_RIGHT_CHILD_SIZE;
+const INTERNAL_NODE_RIGHT_CHILD_OFFSET: usize = NODE_HEADER_SIZE;
 const INTERNAL_NODE_SPACE_FOR_CELLS: usize = PAGE_SIZE - INTERNAL_NODE_HEADER_SIZE;
 const INTERNAL_NODE_CELL_KEY_SIZE: usize = size_of::<i64>();
 const INTERNAL_NODE_CELL_CHILD_SIZE: usize = size_of::<i32>();
 const INTERNAL_NODE_CELL_SIZE: usize = INTERNAL_NODE_CELL_KEY_SIZE + INTERNAL_NODE_CELL_CHILD_SIZE;
 const INTERNAL_NODE_CELL_MAX_NUM: usize = INTERNAL_NODE_SPACE_FOR_CELLS / INTERNAL_NODE_CELL_SIZE;
+const INTERNAL_NODE_CELL_CHILD_OFFSET: usize = 0;
+const INTERNAL_NODE_CELL_KEY_OFFSET: usize =
+    INTERNAL_NODE_CELL_CHILD_OFFSET + INTERNAL_NODE_CELL_CHILD_SIZE;
 
-const SPLIT_RIGHT_LEAF_NODE_NUM: usize = (LEAF_NODE_CELL_MAX_NUM + 1) / 2;
+// page 0 is the file header, the tree starts at page 1
+const HEADER_PAGE_INDEX: usize = 0;
+const ROOT_PAGE_INDEX: usize = 1;
+const HEADER_MAGIC: &[u8; 16] = b"rqlite database\0";
+const HEADER_MAGIC_OFFSET: usize = 0;
+const HEADER_VERSION_OFFSET: usize = HEADER_MAGIC_OFFSET + HEADER_MAGIC.len();
+// 0: no header page, the root sat on page 0
+// 1: header page without a version field
+// 2: versioned header
+// 3: row count in the header
+// 4: no parent field in the node header
+const FORMAT_VERSION: u32 = 4;
+const HEADER_N_ROWS_OFFSET: usize = HEADER_VERSION_OFFSET + size_of::<u32>();
+const HEADER_STATS_OFFSET: usize = 64;
+const STATS_HISTOGRAM_BUCKETS: usize = 10;
+const STATS_N_ROWS_OFFSET: usize = HEADER_STATS_OFFSET;
+const STATS_N_LEAVES_OFFSET: usize = STATS_N_ROWS_OFFSET + size_of::<u64>();
+const STATS_LEAF_FILL_PERCENT_OFFSET: usize = STATS_N_LEAVES_OFFSET + size_of::<u32>();
+const STATS_HISTOGRAM_OFFSET: usize = STATS_LEAF_FILL_PERCENT_OFFSET + size_of::<u32>();
+// sequences sit in the header after the stats, each slot a NUL padded name
+// and the next value to hand out; an empty name marks a free slot, so files
+// from before sequences read as having none
+const HEADER_SEQUENCES_OFFSET: usize = 256;
+const SEQUENCE_NAME_MAX_SIZE: usize = 32;
+const SEQUENCE_SLOT_SIZE: usize = SEQUENCE_NAME_MAX_SIZE + size_of::<i64>();
+const SEQUENCE_MAX_NUM: usize = 32;
+
+const SPLIT_RIGHT_LEAF_NODE_NUM: usize = LEAF_NODE_CELL_MAX_NUM.div_ceil(2);
 const SPLIT_LEFT_LEAF_NODE_NUM: usize = (LEAF_NODE_CELL_MAX_NUM + 1) - SPLIT_RIGHT_LEAF_NODE_NUM;
+const BULK_LOAD_FILL_PERCENT: usize = 100;
+// how full a sequential load leaves the leaf it splits; 100 packs the
+// leaves, lower keeps room for the odd out-of-order insert near the edge
+const LEAF_SPLIT_FILL_PERCENT: usize = 100;
+const READAHEAD_PAGES: usize = 4;
+const PROGRESS_BAR_WIDTH: usize = 30;
+const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const IO_RETRY_MAX: u32 = 3;
+// .export sqlite writes a sqlite3 file with a single table of these columns,
+// id being the integer primary key, i.e. the sqlite rowid
+const SQLITE_PAGE_SIZE: usize = 4096;
+const SQLITE_HEADER_SIZE: usize = 100;
+const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+const SQLITE_TABLE_LEAF: u8 = 0x0d;
+const SQLITE_TABLE_INTERIOR: u8 = 0x05;
+const SQLITE_TABLE_ROOT_PAGE: u32 = 2;
+const SQLITE_TABLE_NAME: &str = "rows";
+const SQLITE_MAX_DEPTH: usize = 32;
+const SQLITE_TABLE_SQL: &str =
+    "CREATE TABLE rows(id INTEGER PRIMARY KEY, name TEXT, description TEXT)";
+// dump stream: magic, then per row a u32 length and the row, then a zero
+// length, the row count and a checksum of everything before it
+const DUMP_MAGIC: &[u8] = b"rqlite dump\0";
+// snapshot file: manifest of magic, snapshot version, database format
+// version, page count, a checksum per page and a hash over the manifest and
+// all pages, followed by the pages as they are in the database file
+const SNAPSHOT_MAGIC: &[u8; 16] = b"rqlite snapshot\0";
+const SNAPSHOT_VERSION: u32 = 1;
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+// longest first, so "<=" isn't read as "<" then "="
+const QUERY_SYMBOLS: [&str; 11] = ["!=", "<>", "<=", ">=", "=", "<", ">", "(", ")", ",", "*"];
+// statements the repl dispatches on, which can't double as aliases
+const STATEMENT_KEYWORDS: [&str; 8] = [
+    "insert", "select", "explain", "pragma", "exists", "analyze", "truncate", "create",
+];
+const COLUMN_NAMES: [&str; 3] = ["id", "name", "description"];
+const SAMPLE_DRAWS_PER_ROW: usize = 4;
+const SORT_RUN_ROWS: usize = 256;
+const BENCH_DEFAULT_ROWS: usize = 500;
+const BENCH_SCAN_ROUNDS: usize = 10;
+
+const USAGE: &str = "USAGE: rqlite [--timeout <ms>] [--slow-ms <ms>] [--dry-run] <database>
+       rqlite bench <database> [--rows N] [--mode seq|random|scan]
+       rqlite migrate <old database> <new database>
+       rqlite recover <damaged database> <new database>
+       rqlite diff [--structure] <database a> <database b>
+       rqlite dump <database> | rqlite restore <new database>
+       rqlite verify <snapshot>
+       rqlite stats [--json] <database>";
+
+const EXIT_USAGE: i32 = 1;
+// any statement failed, or the database could not be opened or closed
+const EXIT_ERROR: i32 = 2;
+const EXIT_CORRUPT: i32 = 3;
 
 const ERR_INSERT_SYNTAX: &str = "ERROR: insert <id> <name> <description>.";
 const ERR_NOT_POSITIVE_ID: &str = "ERROR: id must be greater than 0.";
-const ERR_NAME_TOO_LONG: &str = "ERROR: name too long.";
-const ERR_DESCRIPTION_TOO_LONG: &str = "ERROR: description too long.";
+const ERR_TEXT_NUL: &str = "ERROR: name and description must not contain NUL.";
 const ERR_TABLE_FULL: &str = "ERROR: table reach max size.";
+const ERR_INTERNAL_NODE_FULL: &str = "ERROR: internal node split not supported.";
 const ERR_INVALID_FILE: &str = "ERROR: invalid database file, should be page-aligned.";
+const ERR_IMPORT_SYNTAX: &str =
+    "ERROR: .import [--sorted] [csv options] [csv|sqlite|jsonl] <file>.";
+const ERR_SQLITE_CORRUPT: &str = "ERROR: malformed sqlite3 file.";
+const ERR_BULK_LOAD_NOT_EMPTY: &str = "ERROR: bulk load needs an empty table.";
+const ERR_RECOVER_SYNTAX: &str = "ERROR: .recover <new database>.";
+const ERR_PRAGMA_SYNTAX: &str = "ERROR: pragma <name>[=<value>].";
+const ERR_SNAPSHOT_SYNTAX: &str = "ERROR: .snapshot <file>.";
+const ERR_SNAPSHOT_TRUNCATED: &str = "ERROR: snapshot is truncated.";
+const ERR_RECOVER_NOT_EMPTY: &str = "ERROR: recover needs a new database file.";
+const ERR_EXPORT_SYNTAX: &str = "ERROR: .export sqlite|jsonl <file>.";
+const ERR_EXPLAIN_SYNTAX: &str = "ERROR: explain <statement>.";
+const ERR_EXISTS_SYNTAX: &str = "ERROR: exists <id>.";
+const ERR_OPEN_SYNTAX: &str = "ERROR: .open --as <alias> <database>.";
+const ERR_CLOSE_SYNTAX: &str = "ERROR: .close <alias>.";
+const ERR_HISTORY_SYNTAX: &str = "ERROR: .history [n].";
+const ERR_BOOKMARK_SYNTAX: &str = "ERROR: resume needs a bookmark from a select with a limit.";
+const ERR_BOOKMARK_STALE: &str = "ERROR: bookmark is stale, the table changed since.";
+const ERR_BATCH_SYNTAX: &str = "ERROR: .batch [begin|apply|discard].";
+const ERR_NO_BATCH: &str = "ERROR: no batch is open, start one with .batch begin.";
+const ERR_SEQUENCE_SYNTAX: &str = "ERROR: create sequence <name> [start <n>].";
+const ERR_IN_SYNTAX: &str = "ERROR: in needs a list of ids, like in (1, 2).";
+const ERR_PROFILE_SYNTAX: &str = "ERROR: .profile [table].";
+const ERR_WATCH_SYNTAX: &str = "ERROR: .watch <seconds> [--count N] <select statement>.";
+const ERR_RESTORE_NOT_EMPTY: &str = "ERROR: restore needs a new database file.";
+const ERR_DUMP_TRUNCATED: &str = "ERROR: dump stream is truncated.";
+const ERR_BENCH_NOT_EMPTY: &str = "ERROR: bench needs a new database file.";
 
 // make sure always one byte in size
 #[repr(u8)]
@@ -58,16 +195,96 @@ enum NodeKind {
 
 struct Table {
     root_node_index: usize,
+    // last leaf in key order, reset whenever a split may have replaced it
+    rightmost_leaf_index: Option<usize>,
     pager: Pager,
+    // set by close, so drop does not flush a second time
+    closed: bool,
+    changes: Changes,
+    // statements running past this are stopped, None to never stop them
+    timeout: Option<Duration>,
+    statement_start: Instant,
+    // rows cursors stepped over in the current statement
+    rows_examined: usize,
+    // time the current statement spent merging sorted runs, for explain analyze
+    sort_time: Option<Duration>,
+    // statements taking at least this long are logged to stderr
+    slow: Option<Duration>,
+    leaf_fill_percent: usize,
+    // inserts report what they would do and leave the pages alone
+    dry_run: bool,
+    // set while an import loads its rows
+    progress: Option<Progress>,
+    // inserts queued by .batch begin, written by .batch apply
+    batch: Option<WriteBatch>,
+}
+
+// rows to insert together: either all of them land or none do, and the
+// pages are flushed once at the end
+#[derive(Default)]
+struct WriteBatch {
+    rows: Vec<Row>,
+}
+
+// the cached pages as they were, to put back when a batch fails halfway
+struct Savepoint {
+    n_pages: usize,
+    pages: Vec<Option<Box<Node>>>,
+    dirty: [bool; PAGE_MAX_NUM],
+}
+
+// the progress line of an import on stderr, only drawn on a terminal
+struct Progress {
+    n_rows: usize,
+    n_bytes: u64,
+    start: Instant,
+    last_draw: Instant,
+}
+
+// what last_insert_rowid(), changes() and total_changes() report
+#[derive(Default)]
+struct Changes {
+    last_insert_rowid: i64,
+    // rows written by the last insert or .import
+    changes: usize,
+    total_changes: usize,
 }
 
 struct Pager {
     file: File,
     n_pages: usize,
-    pages: [Option<Node>; PAGE_MAX_NUM],
+    pages: [Option<Box<Node>>; PAGE_MAX_NUM],
+    // pages handed out by get_page since their last flush
+    dirty: [bool; PAGE_MAX_NUM],
+    write_buf: Vec<u8>,
+    read_buf: Vec<u8>,
+    stats: PagerStats,
+    // pages held by a PageHandle, an eviction policy must skip these
+    pin_count: [u32; PAGE_MAX_NUM],
+    // sync the file to disk after every flush
+    synchronous: bool,
 }
 
-#[derive(Clone)]
+// page cache and disk counters, reset by the repl before each statement
+#[derive(Default)]
+struct PagerStats {
+    cache_hits: usize,
+    cache_misses: usize,
+    pages_read: usize,
+    read_calls: usize,
+    pages_written: usize,
+    write_calls: usize,
+    // time spent in file reads and writes
+    io_time: Duration,
+}
+
+// a pinned page, resident in the pager until handed back through unpin
+#[must_use]
+struct PageHandle {
+    page_index: usize,
+}
+
+#[derive(Clone, PartialEq)]
 struct Row {
     id: i64,
     name: [u8; NAME_MAX_SIZE],
@@ -76,691 +293,5159 @@ struct Row {
 
 struct Cursor<'a> {
     table: &'a mut Table,
-    page_index: usize,
+    page: PageHandle,
     cell_index: usize,
     end_of_table: bool,
+    visited: Visited,
+}
+
+// where a limited scan stopped: the next cell to read, and the key that
+// cell held so a resume can tell the tree has changed since
+#[derive(Clone, Copy)]
+struct Bookmark {
+    page_index: usize,
+    cell_index: usize,
+    key: i64,
 }
 
+// a batch of rows in key order, and a bookmark of the row after them
+type RowBatch = (Vec<Row>, Option<Bookmark>);
+
+// rqlite <subcommand> [args], run in place of the repl
+type Subcommand = fn(&[String]) -> Result<(), Box<dyn Error>>;
+
 #[derive(Clone)]
 struct LeafCell {
     key: i64,
     value: Row,
 }
 
-struct InternalCell {
-    child: i32,
-    key: i64,
+// the csv flags of .import
+#[derive(Default, PartialEq)]
+struct CsvOptions {
+    header: bool,
+    delimiter: Option<char>,
+    // the row field each csv column goes to, "-" to drop the column
+    columns: Option<Vec<String>>,
+    null_marker: Option<String>,
+    on_error: OnError,
 }
 
-struct Node {
+// what .import does with a csv line it can't turn into a row
+#[derive(Default, PartialEq)]
+enum OnError {
+    #[default]
+    Abort,
+    Skip,
+    // skip, and write the error to this file
+    Log(String),
+}
+
+// what analyze keeps in the file header, n_leaves is 0 until it first runs
+// since even an empty tree has its root leaf
+#[derive(Default)]
+struct TableStats {
+    n_rows: usize,
+    n_leaves: usize,
+    leaf_fill_percent: usize,
+    // smallest key, then the largest key of each equal-count bucket
+    histogram: [i64; STATS_HISTOGRAM_BUCKETS + 1],
+}
+
+// the pages a walk down the tree or along the leaf chain has reached, so a
+// damaged file whose pointers form a cycle is reported instead of looping
+struct Visited([bool; PAGE_MAX_NUM]);
+
+// one level of the tree for .analyze_tree: its pages, and the children
+// (internal) or cells (leaf) they hold
+struct TreeLevel {
     kind: NodeKind,
-    is_root: bool,
-    parent: i32,
-    n_cells: u32,
-    // following fields only exist in leaf node
-    next_leaf: Option<i32>,
-    leaf_cells: Option<[Option<LeafCell>; LEAF_NODE_CELL_MAX_NUM]>,
-    // following fields only exist in internal node
-    right_child: Option<i32>,
-    internal_cells: Option<[Option<InternalCell>; INTERNAL_NODE_CELL_MAX_NUM]>,
+    n_pages: usize,
+    n_entries: usize,
+}
+
+// what .profile found: the text lengths of every row, and per leaf page the
+// number of cells and the text bytes they hold
+struct Profile {
+    name_lens: Vec<usize>,
+    description_lens: Vec<usize>,
+    leaves: Vec<(usize, usize, usize)>,
+}
+
+// a page that fails validation on load, naming the page and the field
+#[derive(Debug)]
+struct CorruptionError {
+    page_index: usize,
+    field: &'static str,
+    detail: String,
+}
+
+// a statement error at one of its tokens, shown with a caret under it;
+// offset is in bytes into the text that was parsed, and is filled in by
+// Query::parse for the errors its expression parser raises
+#[derive(Debug)]
+struct SyntaxError {
+    message: String,
+    offset: Option<usize>,
+}
+
+// an insert of a key the table already holds
+#[derive(Debug)]
+struct DuplicateKeyError(i64);
+
+// bad command line arguments, printed as the usage text
+#[derive(Debug)]
+struct UsageError;
+
+// statements run in the repl, shared by all sessions like a shell history,
+// kept one per line as <unix seconds> TAB ok|error TAB <statement>
+struct History {
+    file: File,
+    entries: Vec<HistoryEntry>,
+}
+
+struct HistoryEntry {
+    time: u64,
+    ok: bool,
+    statement: String,
+}
+
+struct ReplOptions<'a> {
+    path: &'a str,
+    timeout: Option<Duration>,
+    // statements taking at least this long are logged to stderr
+    slow: Option<Duration>,
+    dry_run: bool,
+}
+
+// a node is the raw page itself, fields are decoded and updated in place
+// through the accessors so loading and flushing is a plain copy of the bytes
+#[repr(align(4096))]
+struct Node {
+    data: [u8; PAGE_SIZE],
 }
 
 impl Table {
     fn new(mut pager: Pager) -> Self {
-        let root_node_index = 0usize;
+        let root_node_index = ROOT_PAGE_INDEX;
         if pager.n_pages == 0 {
+            pager
+                .get_page(HEADER_PAGE_INDEX)
+                .unwrap()
+                .become_header_page();
             let root_node = pager.get_page(root_node_index).unwrap();
             root_node.become_leaf_node();
-            root_node.is_root = true;
+            root_node.set_root(true);
         }
         Table {
             root_node_index,
+            rightmost_leaf_index: None,
             pager,
+            closed: false,
+            changes: Changes::default(),
+            timeout: None,
+            statement_start: Instant::now(),
+            rows_examined: 0,
+            sort_time: None,
+            slow: None,
+            leaf_fill_percent: LEAF_SPLIT_FILL_PERCENT,
+            dry_run: false,
+            progress: None,
+            batch: None,
         }
     }
 
     fn insert(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
-        // TODO: parse ""
-        let args = args
-            .iter()
-            .filter(|str| !str.is_empty())
-            .collect::<Vec<_>>(); // filter out the internal space
-        if args.len() != 3 {
-            return Err(ERR_INSERT_SYNTAX.into());
+        let row = self.parse_row(args, true)?;
+        let id = row.id;
+        self.insert_row(row)?;
+        self.changes.record(id, 1);
+        Ok(())
+    }
+
+    // the row of an insert, its id taken from a sequence when written as
+    // nextval('<name>'); the sequence only moves on when `advance` is set and
+    // the row is valid, so a failed parse doesn't use up a value
+    fn parse_row(&mut self, args: &[&str], advance: bool) -> Result<Row, Box<dyn Error>> {
+        let Some(name) = args.first().and_then(|arg| nextval_name(arg)) else {
+            return Row::parse(args);
+        };
+        let Some((slot, next)) = self.find_sequence(name)? else {
+            return Err(format!("ERROR: no sequence named '{name}'.").into());
+        };
+        let id = next.to_string();
+        let mut args = args.to_vec();
+        args[0] = &id;
+        let row = Row::parse(&args)?;
+        if advance {
+            let Some(after) = next.checked_add(1) else {
+                return Err(format!("ERROR: sequence '{name}' is exhausted.").into());
+            };
+            self.pager
+                .get_page(HEADER_PAGE_INDEX)?
+                .set_sequence(slot, name, after);
         }
-        let id = args[0].parse::<i64>().map_err(|_| ERR_INSERT_SYNTAX)?;
-        if id <= 0 {
-            return Err(ERR_NOT_POSITIVE_ID.into());
+        Ok(row)
+    }
+
+    fn sequences(&mut self) -> Result<Vec<(String, i64)>, Box<dyn Error>> {
+        let header = self.pager.read_page(HEADER_PAGE_INDEX)?;
+        Ok((0..SEQUENCE_MAX_NUM)
+            .filter_map(|slot| header.sequence(slot))
+            .collect())
+    }
+
+    // the slot and next value of a sequence
+    fn find_sequence(&mut self, name: &str) -> Result<Option<(usize, i64)>, Box<dyn Error>> {
+        let header = self.pager.read_page(HEADER_PAGE_INDEX)?;
+        Ok((0..SEQUENCE_MAX_NUM).find_map(|slot| {
+            header
+                .sequence(slot)
+                .filter(|(slot_name, _)| slot_name == name)
+                .map(|(_, next)| (slot, next))
+        }))
+    }
+
+    // create sequence <name> [start <n>]
+    fn create_sequence(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let (name, start) = match args {
+            ["sequence", name] => (*name, 1),
+            ["sequence", name, "start", start] => (
+                *name,
+                start.parse::<i64>().map_err(|_| ERR_SEQUENCE_SYNTAX)?,
+            ),
+            _ => return Err(ERR_SEQUENCE_SYNTAX.into()),
+        };
+        if name.is_empty()
+            || name.len() > SEQUENCE_NAME_MAX_SIZE
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(format!(
+                "ERROR: a sequence name is up to {SEQUENCE_NAME_MAX_SIZE} letters, digits or _."
+            )
+            .into());
         }
-        let name = args[1];
-        if name.len() > NAME_MAX_SIZE {
-            return Err(ERR_NAME_TOO_LONG.into());
+        if start <= 0 {
+            return Err(ERR_NOT_POSITIVE_ID.into());
         }
-        let description = args[2];
-        if description.len() > DESCRIPTION_MAX_SIZE {
-            return Err(ERR_DESCRIPTION_TOO_LONG.into());
+        if self.find_sequence(name)?.is_some() {
+            return Err(format!("ERROR: sequence '{name}' already exists.").into());
         }
-        let mut name_buf = [0u8; NAME_MAX_SIZE];
-        let mut description_buf = [0u8; DESCRIPTION_MAX_SIZE];
-        let max = name.len().min(NAME_MAX_SIZE);
-        name_buf[..max].copy_from_slice(&name.as_bytes()[..max]);
-        let max = description.len().min(DESCRIPTION_MAX_SIZE);
-        description_buf[..max].copy_from_slice(&description.as_bytes()[..max]);
-        let n_cells = self.pager.get_page(self.root_node_index)?.get_n_cells();
-        let mut cursor = Cursor::from(self, id);
-        if cursor.cell_index < n_cells && id == cursor.read_leaf_cell()?.unwrap().key {
-            return Err(format!("ERROR: key '{id}' already exist.").into());
-        }
-        cursor.write_leaf_cell(LeafCell {
-            key: id,
-            value: Row {
-                id,
-                name: name_buf,
-                description: description_buf,
-            },
-        })?;
+        let header = self.pager.read_page(HEADER_PAGE_INDEX)?;
+        let Some(slot) = (0..SEQUENCE_MAX_NUM).find(|&slot| header.sequence(slot).is_none()) else {
+            return Err(
+                format!("ERROR: no room for more than {SEQUENCE_MAX_NUM} sequences.").into(),
+            );
+        };
+        self.pager
+            .get_page(HEADER_PAGE_INDEX)?
+            .set_sequence(slot, name, start);
         Ok(())
     }
 
-    fn select(&mut self) {
-        let mut cursor = Cursor::from_start(self);
-        while !cursor.end_of_table {
-            if let Some(cell) = cursor.read_leaf_cell().unwrap() {
-                println!(
-                    "[{}, {}, {}]",
-                    cell.value.id,
-                    str::from_utf8(&cell.value.name).unwrap(),
-                    str::from_utf8(&cell.value.description).unwrap()
-                )
+    fn insert_row(&mut self, row: Row) -> Result<(), Box<dyn Error>> {
+        let id = row.id;
+        let rightmost_leaf_index = self.get_rightmost_leaf_index()?;
+        let rightmost_leaf = self.pager.read_page(rightmost_leaf_index)?;
+        let rightmost_n_cells = rightmost_leaf.get_n_cells();
+        let mut cursor = if rightmost_n_cells > 0 && id > rightmost_leaf.get_max_key() {
+            // fast path for ascending ids: append to the last leaf without searching
+            let page = self.pager.pin(rightmost_leaf_index)?;
+            Cursor {
+                table: self,
+                page,
+                cell_index: rightmost_n_cells,
+                end_of_table: true,
+                visited: Visited::new(),
             }
-            cursor.advance();
-        }
+        } else {
+            // the cursor sits on the first key >= id in the leaf id belongs
+            // to, so a duplicate can only be the cell right under it
+            let cursor = Cursor::from(self, id)?;
+            if cursor.read_leaf_cell()?.is_some_and(|cell| cell.key == id) {
+                return Err(DuplicateKeyError(id).into());
+            }
+            cursor
+        };
+        cursor.write_leaf_cell(LeafCell::from(&row))?;
+        Ok(())
     }
-}
 
-impl Drop for Table {
-    fn drop(&mut self) {
-        for page_index in 0..self.pager.n_pages {
-            if let Err(error) = self.pager.flush_page_to_file(page_index) {
-                eprintln!("ERROR: db close {error}.");
-                process::exit(1);
+    // inserts a batch in key order, descending from the root again only when
+    // the next key falls outside the leaf the previous one landed in or that
+    // leaf split, and flushes the dirty pages once at the end
+    fn insert_many(
+        &mut self,
+        rows: impl IntoIterator<Item = Row>,
+    ) -> Result<usize, Box<dyn Error>> {
+        let n_rows = self.write_rows(rows)?;
+        self.flush()?;
+        Ok(n_rows)
+    }
+
+    // .batch apply: the inserts of insert_many, rolled back in the page cache
+    // when any of them fails so that the file never sees part of a batch
+    fn apply(&mut self, batch: WriteBatch) -> Result<usize, Box<dyn Error>> {
+        let last_id = batch.rows.last().map(|row| row.id);
+        let savepoint = self.pager.savepoint();
+        let n_rows = match self.write_rows(batch.rows) {
+            Ok(n_rows) => n_rows,
+            Err(error) => {
+                self.pager.rollback(savepoint);
+                self.rightmost_leaf_index = None;
+                return Err(error);
             }
+        };
+        self.flush()?;
+        if let Some(last_id) = last_id {
+            self.changes.record(last_id, n_rows);
         }
+        Ok(n_rows)
     }
-}
 
-impl Pager {
-    fn new(path: &str) -> Result<Self, Box<dyn Error>> {
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .append(true)
-            .open(path)?;
-        let file_size = file.metadata()?.len() as usize;
-        if !file_size.is_multiple_of(PAGE_SIZE) {
-            return Err(ERR_INVALID_FILE.into());
+    // the inserts of insert_many, left in the page cache
+    fn write_rows(&mut self, rows: impl IntoIterator<Item = Row>) -> Result<usize, Box<dyn Error>> {
+        let mut rows = rows.into_iter().collect::<Vec<_>>();
+        rows.sort_by_key(|row| row.id);
+        if let Some(pair) = rows.windows(2).find(|pair| pair[0].id == pair[1].id) {
+            return Err(DuplicateKeyError(pair[0].id).into());
         }
-        Ok(Pager {
-            file,
-            n_pages: file_size / PAGE_SIZE,
-            pages: [const { None }; PAGE_MAX_NUM],
-        })
+        let mut leaf: Option<(usize, Option<i64>)> = None;
+        for (i, row) in rows.iter().enumerate() {
+            self.check_timeout()?;
+            if let Some(progress) = &mut self.progress {
+                progress.update(i);
+            }
+            let (page_index, upper_bound) = match leaf {
+                Some((page_index, upper_bound))
+                    if upper_bound.is_none_or(|upper_bound| row.id <= upper_bound) =>
+                {
+                    (page_index, upper_bound)
+                }
+                _ => self.find_leaf(row.id)?,
+            };
+            let n_pages = self.pager.n_pages;
+            let mut cursor = Cursor::from_leaf_node(self, page_index, row.id)?;
+            if cursor
+                .read_leaf_cell()?
+                .is_some_and(|cell| cell.key == row.id)
+            {
+                return Err(DuplicateKeyError(row.id).into());
+            }
+            cursor.write_leaf_cell(LeafCell::from(row))?;
+            drop(cursor);
+            leaf = (self.pager.n_pages == n_pages).then_some((page_index, upper_bound));
+        }
+        self.rightmost_leaf_index = None;
+        Ok(rows.len())
     }
 
-    fn print_tree(&mut self, page_index: usize, indentation: usize) {
-        self.fetch_page_from_file(page_index).unwrap();
-        let exist = self.pages[page_index].is_some();
-        if !exist {
-            println!("tree node {page_index} not exist");
-            return;
-        }
-        let (node_kind, n_cells) = {
-            let node = self.pages[page_index].as_ref().unwrap();
-            (node.kind.clone(), node.get_n_cells())
+    // pragma <name>[=<value>], returning the value when only asked for it
+    fn pragma(&mut self, text: &str) -> Result<Option<Value>, Box<dyn Error>> {
+        let (name, value) = match text.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (text.trim(), None),
         };
-        match node_kind {
-            NodeKind::Leaf => {
-                print_with_indentation(indentation, format!("- leaf (size {n_cells})").as_ref());
-                for i in 0..n_cells {
-                    let key = {
-                        let node = self.pages[page_index].as_ref().unwrap();
-                        node.read_leaf_cell(i).unwrap().key
-                    };
-                    print_with_indentation(indentation + 1, format!("- {}", key).as_ref());
+        // name and value are one word each, and slices of text
+        if let Some(extra) = [Some(name), value]
+            .into_iter()
+            .flatten()
+            .find_map(|part| part.split_whitespace().nth(1))
+        {
+            let offset = extra.as_ptr() as usize - text.as_ptr() as usize;
+            let message = format!("ERROR: unexpected '{extra}' after pragma.");
+            return Err(SyntaxError::at(offset, message).into());
+        }
+        let ms = |duration: Option<Duration>| duration.map_or(0, |d| d.as_millis() as i64);
+        match (name, value) {
+            ("", _) => return Err(ERR_PRAGMA_SYNTAX.into()),
+            ("page_size", None) => return Ok(Some(Value::Int(PAGE_SIZE as i64))),
+            ("cache_size", None) => return Ok(Some(Value::Int(PAGE_MAX_NUM as i64))),
+            ("page_size" | "cache_size", Some(_)) => {
+                return Err(format!(
+                    "ERROR: {name} is fixed, the cache holds all {PAGE_MAX_NUM} pages a database can have."
+                )
+                .into());
+            }
+            ("synchronous", None) => {
+                let value = if self.pager.synchronous { "on" } else { "off" };
+                return Ok(Some(Value::Text(value.to_string())));
+            }
+            ("synchronous", Some(value)) => {
+                self.pager.synchronous = match value {
+                    "on" | "1" => true,
+                    "off" | "0" => false,
+                    _ => return Err("ERROR: synchronous is on or off.".into()),
                 }
             }
-            NodeKind::Internal => {
-                print_with_indentation(
-                    indentation,
-                    format!("- internal (size {n_cells})").as_ref(),
-                );
-                for i in 0..n_cells {
-                    let (child_page, key) = {
-                        let node = self.pages[page_index].as_ref().unwrap();
-                        let internal_cell = node.read_internal_cell(i).unwrap();
-                        (internal_cell.child as usize, internal_cell.key)
-                    };
-                    self.print_tree(child_page, indentation + 1);
-                    print_with_indentation(indentation + 1, format!("- key {}", key).as_ref());
+            ("timeout", None) => return Ok(Some(Value::Int(ms(self.timeout)))),
+            ("timeout", Some(value)) => self.timeout = parse_timeout(value)?,
+            ("slow_ms", None) => return Ok(Some(Value::Int(ms(self.slow)))),
+            // unlike timeout, 0 logs every statement
+            ("slow_ms", Some(value)) => self.slow = Some(parse_ms(value)?),
+            ("leaf_fill", None) => return Ok(Some(Value::Int(self.leaf_fill_percent as i64))),
+            ("leaf_fill", Some(value)) => {
+                self.leaf_fill_percent = match value.parse() {
+                    Ok(percent @ 50..=100) => percent,
+                    _ => return Err("ERROR: leaf_fill is a percent from 50 to 100.".into()),
                 }
-                let right_child = {
-                    let node = self.pages[page_index].as_ref().unwrap();
-                    node.right_child.unwrap() as usize
-                };
-                self.print_tree(right_child, indentation + 1);
             }
+            _ => return Err(format!("ERROR: unknown pragma '{name}'.").into()),
         }
+        Ok(None)
     }
 
-    fn get_new_page_index(&self) -> usize {
-        self.n_pages
+    // called from the loops a statement can spend long in; rows an import
+    // wrote before stopping stay in the table
+    fn check_timeout(&self) -> Result<(), Box<dyn Error>> {
+        match self.timeout {
+            Some(timeout) if self.statement_start.elapsed() > timeout => Err(format!(
+                "ERROR: statement timed out after {} ms.",
+                timeout.as_millis()
+            )
+            .into()),
+            _ => Ok(()),
+        }
     }
 
-    // must be called when two page exist
-    fn get_two_pages(
-        &mut self,
-        first_page_index: usize,
-        second_page_index: usize,
-    ) -> (&mut Node, &mut Node) {
-        let ptr = self.pages.as_mut_ptr();
-        unsafe {
-            let first_page = (*ptr.add(first_page_index)).as_mut().unwrap();
-            let second_page = (*ptr.add(second_page_index)).as_mut().unwrap();
-            (first_page, second_page)
+    // the leaf `key` belongs to and the largest key routed to it, None when
+    // it is the rightmost leaf
+    fn find_leaf(&mut self, key: i64) -> Result<(usize, Option<i64>), Box<dyn Error>> {
+        let mut page_index = self.root_node_index;
+        let mut upper_bound = None;
+        let mut visited = Visited::new();
+        loop {
+            visited.visit(page_index, "child")?;
+            let node = self.pager.read_page(page_index)?;
+            if let NodeKind::Leaf = node.kind() {
+                return Ok((page_index, upper_bound));
+            }
+            let cell_index = node.find_internal_cell(key);
+            if cell_index < node.get_n_cells() {
+                upper_bound = Some(node.internal_key(cell_index));
+            }
+            page_index = node.get_child_page_index(cell_index);
         }
     }
 
-    fn get_page(&mut self, page_index: usize) -> Result<&mut Node, Box<dyn Error>> {
-        if page_index >= PAGE_MAX_NUM {
+    // a descent and a search of the leaf keys, the row itself is never decoded
+    fn contains_key(&mut self, id: i64) -> Result<bool, Box<dyn Error>> {
+        let (page_index, _) = self.find_leaf(id)?;
+        let node = self.pager.read_page(page_index)?;
+        Ok(node.find_leaf_key(id).is_some())
+    }
+
+    // the rows with these ids, None for the missing ones, in the order asked;
+    // the ids are looked up in key order so that ids sharing a leaf share
+    // one descent
+    fn get_many(&mut self, ids: &[i64]) -> Result<Vec<Option<Row>>, Box<dyn Error>> {
+        let mut order = (0..ids.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| ids[i]);
+        let mut rows = vec![None; ids.len()];
+        // the leaf of the last descent and the largest key routed to it
+        let mut leaf: Option<(usize, Option<i64>)> = None;
+        for i in order {
+            let id = ids[i];
+            let page_index = match leaf {
+                Some((page_index, upper_bound)) if upper_bound.is_none_or(|key| id <= key) => {
+                    page_index
+                }
+                _ => {
+                    let (page_index, upper_bound) = self.find_leaf(id)?;
+                    leaf = Some((page_index, upper_bound));
+                    page_index
+                }
+            };
+            let node = self.pager.read_page(page_index)?;
+            rows[i] = node
+                .find_leaf_key(id)
+                .and_then(|cell_index| node.read_leaf_cell(cell_index))
+                .map(|cell| cell.value);
+        }
+        Ok(rows)
+    }
+
+    // the internal node pointing at `page_index`, found by descending towards
+    // `key`, one of the keys the page holds
+    fn find_parent(&mut self, page_index: usize, key: i64) -> Result<usize, Box<dyn Error>> {
+        let mut parent_index = self.root_node_index;
+        let mut visited = Visited::new();
+        loop {
+            visited.visit(parent_index, "child")?;
+            let node = self.pager.read_page(parent_index)?;
+            if let NodeKind::Leaf = node.kind() {
+                let detail = format!("page {page_index} not reached from the root");
+                return Err(CorruptionError::new(parent_index, "child", detail).into());
+            }
+            let child = node.get_child_page_index(node.find_internal_cell(key));
+            if child == page_index {
+                return Ok(parent_index);
+            }
+            parent_index = child;
+        }
+    }
+
+    // builds the tree bottom-up from rows sorted by id: leaves are packed left
+    // to right, then each internal level is built from the one below it
+    fn bulk_load(&mut self, rows: impl IntoIterator<Item = Row>) -> Result<usize, Box<dyn Error>> {
+        let rows = rows.into_iter().collect::<Vec<_>>();
+        let root_node = self.pager.read_page(self.root_node_index)?;
+        if !matches!(root_node.kind(), NodeKind::Leaf) || root_node.get_n_cells() != 0 {
+            return Err(ERR_BULK_LOAD_NOT_EMPTY.into());
+        }
+        for pair in rows.windows(2) {
+            if pair[0].id >= pair[1].id {
+                return Err(format!(
+                    "ERROR: bulk load needs ascending ids, got '{}' after '{}'.",
+                    pair[1].id, pair[0].id
+                )
+                .into());
+            }
+        }
+        let leaf_fill = (LEAF_NODE_CELL_MAX_NUM * BULK_LOAD_FILL_PERCENT / 100).max(1);
+        if rows.len() <= leaf_fill {
+            let root_node = self.pager.get_page(self.root_node_index)?;
+            for row in &rows {
+                root_node.push_leaf_cell(&LeafCell::from(row).encode());
+            }
+            self.add_rows(rows.len())?;
+            return Ok(rows.len());
+        }
+        let fanout = INTERNAL_NODE_CELL_MAX_NUM + 1;
+        let mut n_nodes = rows.len().div_ceil(leaf_fill);
+        let mut needed_pages = n_nodes;
+        while n_nodes > fanout {
+            n_nodes = n_nodes.div_ceil(fanout);
+            needed_pages += n_nodes;
+        }
+        if self.pager.get_new_page_index() + needed_pages > PAGE_MAX_NUM {
             return Err(ERR_TABLE_FULL.into());
         }
-        if self.pages[page_index].is_some() {
-            return Ok(self.pages[page_index].as_mut().unwrap());
+        // (page index, max key) of every node on the level being built
+        let mut level = Vec::new();
+        for (i, chunk) in rows.chunks(leaf_fill).enumerate() {
+            if let Some(progress) = &mut self.progress {
+                progress.update(i * leaf_fill);
+            }
+            let page_index = self.pager.get_new_page_index();
+            let leaf = self.pager.get_page(page_index)?;
+            leaf.become_leaf_node();
+            for row in chunk {
+                leaf.push_leaf_cell(&LeafCell::from(row).encode());
+            }
+            if let Some(&(prev_page_index, _)) = level.last() {
+                self.pager
+                    .get_page(prev_page_index)?
+                    .set_next_leaf(page_index as i32);
+            }
+            level.push((page_index, chunk.last().unwrap().id));
         }
-        if page_index < self.n_pages {
-            self.fetch_page_from_file(page_index)?;
-        } else {
-            self.n_pages = page_index + 1;
-            self.pages[page_index] = Some(Node {
-                kind: NodeKind::Leaf,
-                is_root: false,
-                parent: NOT_EXIST,
-                n_cells: 0,
-                next_leaf: None,
-                leaf_cells: None,
-                right_child: None,
-                internal_cells: None,
-            });
+        while level.len() > fanout {
+            let mut next_level = Vec::new();
+            for children in level.chunks(fanout) {
+                let page_index = self.pager.get_new_page_index();
+                self.build_internal_node(page_index, children)?;
+                next_level.push((page_index, children.last().unwrap().1));
+            }
+            level = next_level;
         }
-        Ok(self.pages[page_index].as_mut().unwrap())
+        // the top level always fits in the root, which keeps its page
+        self.build_internal_node(self.root_node_index, &level)?;
+        self.rightmost_leaf_index = None;
+        self.add_rows(rows.len())?;
+        Ok(rows.len())
     }
 
-    fn fetch_page_from_file(&mut self, page_index: usize) -> Result<(), Box<dyn Error>> {
-        if self.pages[page_index].is_none() {
-            self.pages[page_index] = Some(Node::read_at(&self.file, page_index * PAGE_SIZE)?);
+    fn build_internal_node(
+        &mut self,
+        page_index: usize,
+        children: &[(usize, i64)],
+    ) -> Result<(), Box<dyn Error>> {
+        let node = self.pager.get_page(page_index)?;
+        node.become_internal_node();
+        let (&(right_child, _), cells) = children.split_last().unwrap();
+        for (i, &(child, key)) in cells.iter().enumerate() {
+            node.put_internal_cell(i, child as i32, key);
         }
+        node.set_n_cells(cells.len());
+        node.set_right_child(right_child as i32);
         Ok(())
     }
 
-    fn flush_page_to_file(&mut self, page_index: usize) -> Result<(), Box<dyn Error>> {
-        match self.pages[page_index].as_mut() {
-            None => Ok(()),
-            Some(page) => Ok(page.write_at(&self.file, page_index * PAGE_SIZE)?),
+    // .import [--sorted] [csv options] [csv|sqlite|jsonl] <file>, csv being
+    // the default; returns the rows imported and the csv lines skipped
+    fn import(&mut self, args: &[&str]) -> Result<(usize, usize), Box<dyn Error>> {
+        let mut sorted = false;
+        let mut options = CsvOptions::default();
+        let mut positional = Vec::new();
+        let first_arg = args.first().copied().unwrap_or_default();
+        let mut args = args.iter().copied();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(ERR_IMPORT_SYNTAX);
+            match arg {
+                "--sorted" => sorted = true,
+                "--header" => options.header = true,
+                "--delimiter" => options.delimiter = Some(parse_delimiter(value()?)?),
+                "--columns" => {
+                    let columns = value()?.split(',').map(|column| column.trim().to_string());
+                    options.columns = Some(columns.collect());
+                }
+                "--null" => options.null_marker = Some(value()?.to_string()),
+                "--on-error" => {
+                    options.on_error = match value()? {
+                        "abort" => OnError::Abort,
+                        "skip" => OnError::Skip,
+                        value => match value.strip_prefix("log=") {
+                            Some(path) => OnError::Log(path.to_string()),
+                            None => return Err("ERROR: --on-error abort|skip|log=<file>.".into()),
+                        },
+                    }
+                }
+                arg if arg.starts_with("--") => {
+                    return Err(format!("ERROR: unknown import option '{arg}'.").into());
+                }
+                arg => positional.push(arg),
+            }
         }
-    }
-}
-
-impl<'a> Cursor<'a> {
-    fn from(table: &'a mut Table, key: i64) -> Self {
-        let root_index = table.root_node_index;
-        let root_node = table.pager.get_page(root_index).unwrap();
-        match root_node.kind {
-            NodeKind::Leaf => Self::from_leaf_node(table, root_index, key),
-            NodeKind::Internal => Self::from_internal_node(table, root_index, key),
+        let (format, path) = match positional[..] {
+            [path] => ("csv", path),
+            [format, path] => (format, path),
+            [_, _, extra, ..] => {
+                // the args are slices of one line
+                let offset = extra.as_ptr() as usize - first_arg.as_ptr() as usize;
+                let message = format!("ERROR: unexpected '{extra}' after .import.");
+                return Err(SyntaxError::at(offset, message).into());
+            }
+            _ => return Err(ERR_IMPORT_SYNTAX.into()),
+        };
+        if format != "csv" && options != CsvOptions::default() {
+            return Err(format!("ERROR: csv options don't apply to {format} import.").into());
+        }
+        let (rows, n_skipped) = match format {
+            "csv" => read_csv(path, &options)?,
+            "sqlite" => (read_sqlite(path)?, 0),
+            "jsonl" => (read_jsonl(path)?, 0),
+            _ => return Err(format!("ERROR: unknown import format '{format}'.").into()),
+        };
+        let last_id = rows.iter().map(|row| row.id).max();
+        self.progress = Progress::start(rows.len(), fs::metadata(path)?.len());
+        let result = if sorted {
+            self.bulk_load(rows)
+        } else {
+            self.insert_many(rows)
+        };
+        if let Some(progress) = self.progress.take() {
+            progress.finish(result.as_ref().ok().copied());
         }
+        let n_rows = result?;
+        if let Some(last_id) = last_id {
+            self.changes.record(last_id, n_rows);
+        }
+        Ok((n_rows, n_skipped))
     }
 
-    fn from_start(table: &'a mut Table) -> Self {
-        Self::from(table, 0)
+    // .export <format> <file>, the file must not exist yet
+    fn export(&mut self, args: &[&str]) -> Result<usize, Box<dyn Error>> {
+        let [format, path] = args else {
+            return Err(ERR_EXPORT_SYNTAX.into());
+        };
+        let mut rows = Vec::new();
+        self.scan(|cell| rows.push(cell.value.clone()))?;
+        match *format {
+            "sqlite" => write_sqlite(&rows, path)?,
+            "jsonl" => write_jsonl(&rows, path)?,
+            _ => return Err(format!("ERROR: unknown export format '{format}'.").into()),
+        }
+        Ok(rows.len())
     }
 
-    fn from_leaf_node(table: &'a mut Table, page_index: usize, key: i64) -> Self {
-        let node = table.pager.get_page(page_index).unwrap();
-        let n_cells = node.get_n_cells();
-        let mut left = 0usize;
-        let mut right = n_cells;
-        while left != right {
-            let mid = (left + right) / 2;
-            let cell_key = node.read_leaf_cell(mid).unwrap().key;
-            if key == cell_key {
-                return Cursor {
-                    table,
-                    page_index,
-                    cell_index: mid,
-                    end_of_table: false,
-                };
-            } else if key < cell_key {
-                right = mid;
-            } else {
-                left = mid + 1;
+    // walks the leaves once and stores row count, fill and key histogram in
+    // the header page
+    fn analyze(&mut self) -> Result<TableStats, Box<dyn Error>> {
+        let mut keys = Vec::new();
+        let mut n_leaves = 0;
+        let (mut page_index, _) = self.find_leaf(0)?;
+        let mut visited = Visited::new();
+        loop {
+            visited.visit(page_index, "next_leaf")?;
+            let node = self.pager.read_page(page_index)?;
+            keys.extend((0..node.get_n_cells()).map(|i| node.leaf_key(i)));
+            n_leaves += 1;
+            if node.next_leaf() == NOT_EXIST {
+                break;
             }
+            page_index = node.next_leaf() as usize;
         }
-        Cursor {
-            table,
-            page_index,
-            cell_index: left,
-            end_of_table: key == 0 && n_cells == 0,
+        let mut stats = TableStats {
+            n_rows: keys.len(),
+            n_leaves,
+            leaf_fill_percent: keys.len() * 100 / (n_leaves * LEAF_NODE_CELL_MAX_NUM),
+            histogram: [0; STATS_HISTOGRAM_BUCKETS + 1],
+        };
+        if !keys.is_empty() {
+            stats.histogram[0] = keys[0];
+            for i in 1..=STATS_HISTOGRAM_BUCKETS {
+                stats.histogram[i] = keys[(i * keys.len()).div_ceil(STATS_HISTOGRAM_BUCKETS) - 1];
+            }
         }
+        self.pager.get_page(HEADER_PAGE_INDEX)?.write_stats(&stats);
+        Ok(stats)
     }
 
-    fn from_internal_node(table: &'a mut Table, page_index: usize, key: i64) -> Self {
-        let node = table.pager.get_page(page_index).unwrap();
-        let n_cells = node.get_n_cells();
-        let mut left = 0usize;
-        let mut right = n_cells;
-        while left != right {
-            let mid = (left + right) / 2;
-            let cell_key = node.read_internal_cell(mid).unwrap().key;
-            if key <= cell_key {
-                right = mid;
-            } else {
-                left = mid + 1;
+    // .profile: scan every leaf in key order for the lengths of the text columns
+    fn profile(&mut self) -> Result<Profile, Box<dyn Error>> {
+        let mut profile = Profile {
+            name_lens: Vec::new(),
+            description_lens: Vec::new(),
+            leaves: Vec::new(),
+        };
+        let (mut page_index, _) = self.find_leaf(i64::MIN)?;
+        let mut visited = Visited::new();
+        loop {
+            visited.visit(page_index, "next_leaf")?;
+            let node = self.pager.read_page(page_index)?;
+            let n_cells = node.get_n_cells();
+            let mut text_bytes = 0;
+            for i in 0..n_cells {
+                let row = LeafCell::decode(node.leaf_cell_bytes(i)).value;
+                let (name_len, description_len) = (row.name().len(), row.description().len());
+                profile.name_lens.push(name_len);
+                profile.description_lens.push(description_len);
+                text_bytes += name_len + description_len;
             }
+            profile.leaves.push((page_index, n_cells, text_bytes));
+            if node.next_leaf() == NOT_EXIST {
+                break;
+            }
+            page_index = node.next_leaf() as usize;
         }
-        let child_page_index = node.get_child_page_index(left);
-        let child_node = table.pager.get_page(child_page_index).unwrap();
-        match child_node.kind {
-            NodeKind::Leaf => Self::from_leaf_node(table, child_page_index, key),
-            NodeKind::Internal => Self::from_internal_node(table, child_page_index, key),
-        }
+        profile.name_lens.sort_unstable();
+        profile.description_lens.sort_unstable();
+        Ok(profile)
     }
 
-    fn advance(&mut self) {
-        self.cell_index += 1;
-        let node = self.table.pager.get_page(self.page_index).unwrap();
-        let end_of_cell = self.cell_index >= node.get_n_cells();
-        if end_of_cell {
-            let next_leaf = node.next_leaf.unwrap();
-            if next_leaf != NOT_EXIST {
-                self.page_index = next_leaf as usize;
-                self.cell_index = 0;
-            } else {
-                self.end_of_table = true;
+    // explain <statement>: the access path the statement would take, without
+    // running it
+    fn explain(&mut self, args: &[&str]) -> Result<Vec<String>, Box<dyn Error>> {
+        let Some((&keyword, args)) = args.split_first() else {
+            return Err(ERR_EXPLAIN_SYNTAX.into());
+        };
+        let depth = self.depth()?;
+        let mut plan = Vec::new();
+        match keyword {
+            "insert" => {
+                let row = self.parse_row(args, false)?;
+                let rightmost_leaf_index = self.get_rightmost_leaf_index()?;
+                let rightmost_leaf = self.pager.read_page(rightmost_leaf_index)?;
+                let (leaf_index, pages) =
+                    if rightmost_leaf.get_n_cells() > 0 && row.id > rightmost_leaf.get_max_key() {
+                        plan.push(format!(
+                            "append to rightmost leaf page {rightmost_leaf_index}"
+                        ));
+                        (rightmost_leaf_index, 1)
+                    } else {
+                        let (leaf_index, _) = self.find_leaf(row.id)?;
+                        plan.push(format!("primary key seek to leaf page {leaf_index}"));
+                        (leaf_index, depth)
+                    };
+                if self.pager.read_page(leaf_index)?.get_n_cells() >= LEAF_NODE_CELL_MAX_NUM {
+                    plan.push(format!("split leaf page {leaf_index}"));
+                }
+                plan.push(format!("pages expected: {pages}"));
             }
+            "select" | "analyze" => {
+                let query = match keyword {
+                    "select" => Some(Query::parse(&args.join(" "))?),
+                    _ => None,
+                };
+                if let Some(query) = &query
+                    && let Some(steps) = self.explain_shortcut(query, depth)?
+                {
+                    return Ok(steps);
+                }
+                let (leaf_index, _) = self.find_leaf(0)?;
+                plan.push(format!(
+                    "full scan of primary key from leaf page {leaf_index}"
+                ));
+                if let Some(query) = query {
+                    if !query.filter.is_empty() {
+                        plan.push("filter rows by where clause".to_string());
+                    }
+                    if query.order_by.is_some() {
+                        plan.push(format!(
+                            "merge sort for order by, spilling runs of {SORT_RUN_ROWS} rows"
+                        ));
+                    }
+                }
+                if keyword == "analyze" {
+                    plan.push(format!("write stats to header page {HEADER_PAGE_INDEX}"));
+                }
+                let stats = self.stats()?;
+                if stats.n_leaves == 0 {
+                    plan.push("pages expected: unknown, run analyze.".to_string());
+                } else {
+                    plan.push(format!("pages expected: {}", depth - 1 + stats.n_leaves));
+                }
+            }
+            _ => return Err(format!("ERROR: cannot explain '{keyword}'.").into()),
         }
+        Ok(plan)
     }
 
-    // actually don't need &mut here, but for the sake of compiler's complain
-    fn read_leaf_cell(&mut self) -> Result<Option<&LeafCell>, Box<dyn Error>> {
-        Ok(self
+    // the plan of a select that answers without a full scan, None otherwise
+    fn explain_shortcut(
+        &mut self,
+        query: &Query,
+        depth: usize,
+    ) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+        let plan = match query.access {
+            Access::Scan if query.count && query.filter.is_empty() => vec![
+                format!("row count from header page {HEADER_PAGE_INDEX}"),
+                "pages expected: 1".to_string(),
+            ],
+            Access::Scan => return Ok(None),
+            Access::Sample(n) => vec![
+                format!("random descent from the root for each of {n} rows"),
+                format!("pages expected: about {}", depth * n),
+            ],
+            Access::First => vec![
+                format!(
+                    "descend to leftmost leaf page {}",
+                    self.find_leaf(i64::MIN)?.0
+                ),
+                format!("pages expected: {depth}"),
+            ],
+            Access::Last => vec![
+                format!(
+                    "descend to rightmost leaf page {}",
+                    self.find_leaf(i64::MAX)?.0
+                ),
+                format!("pages expected: {depth}"),
+            ],
+            Access::In(ref ids) => {
+                let mut ids = ids.clone();
+                ids.sort_unstable();
+                ids.dedup();
+                let mut leaves = Vec::new();
+                for id in ids {
+                    let (leaf_index, _) = self.find_leaf(id)?;
+                    if leaves.last() != Some(&leaf_index) {
+                        leaves.push(leaf_index);
+                    }
+                }
+                let leaf_list = leaves.iter().map(usize::to_string).collect::<Vec<_>>();
+                vec![
+                    format!("primary key seeks to leaf pages {}", leaf_list.join(", ")),
+                    format!("pages expected: about {}", depth + leaves.len() - 1),
+                ]
+            }
+            Access::After(id, limit) => {
+                let (leaf_index, _) = self.find_leaf(id.saturating_add(1))?;
+                let mut plan = vec![format!(
+                    "primary key seek past {id} to leaf page {leaf_index}"
+                )];
+                plan.extend(leaf_scan_plan(limit, depth));
+                plan
+            }
+            Access::Resume(ref bookmark, limit) => {
+                let mut plan = vec![format!(
+                    "resume at leaf page {} cell {}, no descent",
+                    bookmark.page_index, bookmark.cell_index
+                )];
+                plan.extend(leaf_scan_plan(limit, 1));
+                plan
+            }
+        };
+        Ok(Some(plan))
+    }
+
+    // number of pages from the root down to a leaf, the same on every path
+    fn depth(&mut self) -> Result<usize, Box<dyn Error>> {
+        let mut depth = 1;
+        let mut page_index = self.root_node_index;
+        let mut visited = Visited::new();
+        loop {
+            visited.visit(page_index, "child")?;
+            let node = self.pager.read_page(page_index)?;
+            match node.kind() {
+                NodeKind::Leaf => return Ok(depth),
+                NodeKind::Internal => page_index = node.right_child() as usize,
+            }
+            depth += 1;
+        }
+    }
+
+    // .analyze_tree: walk the tree one level at a time from the root
+    fn tree_levels(&mut self) -> Result<Vec<TreeLevel>, Box<dyn Error>> {
+        let mut levels = Vec::new();
+        let mut level = vec![self.root_node_index];
+        let mut visited = Visited::new();
+        while !level.is_empty() {
+            let mut next_level = Vec::new();
+            let mut tree_level = TreeLevel {
+                kind: NodeKind::Leaf,
+                n_pages: level.len(),
+                n_entries: 0,
+            };
+            for page_index in level {
+                visited.visit(page_index, "child")?;
+                let node = self.pager.read_page(page_index)?;
+                let n_cells = node.get_n_cells();
+                match node.kind() {
+                    NodeKind::Internal => {
+                        tree_level.kind = NodeKind::Internal;
+                        tree_level.n_entries += n_cells + 1;
+                        next_level.extend((0..n_cells).map(|i| node.internal_child(i) as usize));
+                        next_level.push(node.right_child() as usize);
+                    }
+                    NodeKind::Leaf => tree_level.n_entries += n_cells,
+                }
+            }
+            levels.push(tree_level);
+            level = next_level;
+        }
+        Ok(levels)
+    }
+
+    // the rows at either end of the key order, straight from the edge leaves
+    fn first(&mut self) -> Result<Option<Row>, Box<dyn Error>> {
+        let (page_index, _) = self.find_leaf(i64::MIN)?;
+        let node = self.pager.read_page(page_index)?;
+        Ok(node.read_leaf_cell(0).map(|cell| cell.value))
+    }
+
+    fn last(&mut self) -> Result<Option<Row>, Box<dyn Error>> {
+        let page_index = self.get_rightmost_leaf_index()?;
+        let node = self.pager.read_page(page_index)?;
+        let n_cells = node.get_n_cells();
+        Ok(n_cells
+            .checked_sub(1)
+            .and_then(|i| node.read_leaf_cell(i))
+            .map(|cell| cell.value))
+    }
+
+    // what insert would do, worked out from reads alone, failing the same way
+    // the insert itself would
+    fn dry_run_insert(&mut self, args: &[&str]) -> Result<String, Box<dyn Error>> {
+        let row = self.parse_row(args, false)?;
+        if self.contains_key(row.id)? {
+            return Err(DuplicateKeyError(row.id).into());
+        }
+        let (leaf_index, _) = self.find_leaf(row.id)?;
+        let leaf = self.pager.read_page(leaf_index)?;
+        let (n_cells, is_root) = (leaf.get_n_cells(), leaf.is_root());
+        let outcome = format!("1 row would go into leaf page {leaf_index}");
+        if n_cells < LEAF_NODE_CELL_MAX_NUM {
+            return Ok(outcome);
+        }
+        let needed_pages = if is_root { 2 } else { 1 };
+        if self.pager.get_new_page_index() + needed_pages > PAGE_MAX_NUM {
+            return Err(ERR_TABLE_FULL.into());
+        }
+        if !is_root {
+            let parent_index = self.find_parent(leaf_index, row.id)?;
+            if self.pager.read_page(parent_index)?.get_n_cells() >= INTERNAL_NODE_CELL_MAX_NUM {
+                return Err(ERR_INTERNAL_NODE_FULL.into());
+            }
+        }
+        if is_root {
+            return Ok(format!(
+                "{outcome}, splitting the root into 2 new leaf pages"
+            ));
+        }
+        Ok(format!("{outcome}, splitting it onto 1 new page"))
+    }
+
+    fn reload(&mut self) -> Result<(), Box<dyn Error>> {
+        self.flush()?;
+        self.pager.reload()?;
+        self.rightmost_leaf_index = None;
+        Ok(())
+    }
+
+    // keyset pagination: the next page starts after the last id of the one
+    // before, so rows inserted meanwhile neither shift nor repeat it
+    fn after(&mut self, id: i64, limit: Option<usize>) -> Result<RowBatch, Box<dyn Error>> {
+        let Some(key) = id.checked_add(1) else {
+            return Ok((Vec::new(), None));
+        };
+        Cursor::from(self, key)?.take(limit)
+    }
+
+    // select resume <bookmark>: carry on from where a limited select stopped
+    fn resume(
+        &mut self,
+        bookmark: &Bookmark,
+        limit: Option<usize>,
+    ) -> Result<RowBatch, Box<dyn Error>> {
+        Cursor::resume(self, bookmark)?.take(limit)
+    }
+
+    // up to n distinct rows in key order, each from a random path down the
+    // tree; every child of a node is equally likely, so rows in sparser
+    // leaves come up a little more often
+    fn sample(&mut self, n: usize) -> Result<Vec<Row>, Box<dyn Error>> {
+        let n = n.min(self.count()?);
+        let mut rng = Rng::from_time();
+        let mut rows = BTreeMap::new();
+        // repeats are drawn again, a bounded number of times
+        for _ in 0..n * SAMPLE_DRAWS_PER_ROW {
+            if rows.len() == n {
+                break;
+            }
+            let mut page_index = self.root_node_index;
+            let mut visited = Visited::new();
+            loop {
+                visited.visit(page_index, "child")?;
+                let node = self.pager.read_page(page_index)?;
+                let n_cells = node.get_n_cells();
+                match node.kind() {
+                    NodeKind::Internal => {
+                        page_index = node.get_child_page_index(rng.below(n_cells + 1));
+                    }
+                    NodeKind::Leaf => {
+                        if n_cells > 0 {
+                            let cell = node.read_leaf_cell(rng.below(n_cells)).unwrap();
+                            rows.insert(cell.key, cell.value);
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(rows.into_values().collect())
+    }
+
+    // kept in the header by every insert, so counting never scans
+    fn count(&mut self) -> Result<usize, Box<dyn Error>> {
+        Ok(self.pager.read_page(HEADER_PAGE_INDEX)?.n_rows())
+    }
+
+    // truncate: the tree is all the file holds past the header, so instead of
+    // freeing its pages one by one the root becomes an empty leaf and the
+    // file is cut back to end there
+    fn truncate(&mut self) -> Result<usize, Box<dyn Error>> {
+        let n_rows = self.count()?;
+        self.pager
+            .get_page(self.root_node_index)?
+            .become_leaf_node();
+        let header = self.pager.get_page(HEADER_PAGE_INDEX)?;
+        header.set_n_rows(0);
+        header.write_stats(&TableStats::default());
+        self.pager.truncate(self.root_node_index + 1)?;
+        self.rightmost_leaf_index = None;
+        self.changes.changes = n_rows;
+        self.changes.total_changes += n_rows;
+        Ok(n_rows)
+    }
+
+    // .optimize: bulk loads the rows again into full leaves, which merges
+    // underfull ones and rebuilds the separators above them; the new tree is
+    // put together in the cache and only flushed once complete, and the file
+    // shrinks to the pages it still uses; returns the page counts before and
+    // after
+    fn optimize(&mut self) -> Result<(usize, usize), Box<dyn Error>> {
+        let n_pages = self.pager.n_pages;
+        let mut rows = Vec::new();
+        self.scan(|cell| rows.push(cell.value.clone()))?;
+        let analyzed = self.stats()?.n_leaves != 0;
+        let savepoint = self.pager.savepoint();
+        if let Err(error) = self.rebuild(rows, analyzed) {
+            self.pager.rollback(savepoint);
+            self.rightmost_leaf_index = None;
+            return Err(error);
+        }
+        self.pager.truncate(self.pager.n_pages)?;
+        Ok((n_pages, self.pager.n_pages))
+    }
+
+    fn rebuild(&mut self, rows: Vec<Row>, analyze: bool) -> Result<(), Box<dyn Error>> {
+        self.pager
+            .get_page(self.root_node_index)?
+            .become_leaf_node();
+        self.pager.get_page(HEADER_PAGE_INDEX)?.set_n_rows(0);
+        self.pager.drop_pages(self.root_node_index + 1);
+        self.bulk_load(rows)?;
+        // the leaf count and fill changed, so stats from before are redone
+        if analyze {
+            self.analyze()?;
+        }
+        Ok(())
+    }
+
+    fn add_rows(&mut self, n_rows: usize) -> Result<(), Box<dyn Error>> {
+        let header = self.pager.get_page(HEADER_PAGE_INDEX)?;
+        header.set_n_rows(header.n_rows() + n_rows);
+        Ok(())
+    }
+
+    fn stats(&mut self) -> Result<TableStats, Box<dyn Error>> {
+        Ok(self.pager.read_page(HEADER_PAGE_INDEX)?.read_stats())
+    }
+
+    fn get_rightmost_leaf_index(&mut self) -> Result<usize, Box<dyn Error>> {
+        if let Some(page_index) = self.rightmost_leaf_index {
+            return Ok(page_index);
+        }
+        let mut page_index = self.root_node_index;
+        let mut visited = Visited::new();
+        loop {
+            visited.visit(page_index, "child")?;
+            let node = self.pager.read_page(page_index)?;
+            match node.kind() {
+                NodeKind::Leaf => break,
+                NodeKind::Internal => page_index = node.right_child() as usize,
+            }
+        }
+        self.rightmost_leaf_index = Some(page_index);
+        Ok(page_index)
+    }
+
+    fn select(&mut self, query: &Query) -> Result<(), Box<dyn Error>> {
+        let bookmark = self.select_into(query, |values| {
+            let values = values.iter().map(Value::to_string).collect::<Vec<_>>();
+            println!("[{}]", values.join(", "));
+        })?;
+        if let Some(bookmark) = bookmark {
+            println!("bookmark: {bookmark}");
+        }
+        Ok(())
+    }
+
+    // runs a select, handing each result row to emit; returns the bookmark
+    // of a limited select that stopped short of the end
+    fn select_into(
+        &mut self,
+        query: &Query,
+        mut emit: impl FnMut(Vec<Value>),
+    ) -> Result<Option<Bookmark>, Box<dyn Error>> {
+        if query.count {
+            let n_rows = if query.filter.is_empty() {
+                self.count()?
+            } else {
+                let mut n_rows = 0;
+                let mut cursor = Cursor::from_start(self)?;
+                while !cursor.end_of_table {
+                    if let Some(cell) = cursor.read_leaf_cell()?
+                        && query
+                            .eval(Some(&cell.value), &cursor.table.changes)?
+                            .is_some()
+                    {
+                        n_rows += 1;
+                    }
+                    cursor.advance()?;
+                }
+                n_rows
+            };
+            emit(vec![Value::Int(n_rows as i64)]);
+            return Ok(None);
+        }
+        let mut bookmark = None;
+        let rows = match query.access {
+            Access::Scan => None,
+            Access::In(ref ids) => {
+                let mut ids = ids.clone();
+                ids.sort_unstable();
+                ids.dedup();
+                Some(self.get_many(&ids)?.into_iter().flatten().collect())
+            }
+            Access::Sample(n) => Some(self.sample(n)?),
+            Access::First => Some(self.first()?.into_iter().collect()),
+            Access::Last => Some(self.last()?.into_iter().collect()),
+            Access::After(id, limit) => {
+                let (rows, next) = self.after(id, limit)?;
+                bookmark = next;
+                Some(rows)
+            }
+            Access::Resume(ref from, limit) => {
+                let (rows, next) = self.resume(from, limit)?;
+                bookmark = next;
+                Some(rows)
+            }
+        };
+        if let Some(rows) = rows {
+            for row in rows {
+                if let Some(values) = query.eval(Some(&row), &self.changes)? {
+                    emit(values);
+                }
+            }
+            return Ok(bookmark);
+        }
+        if !query.uses_columns() {
+            if let Some(values) = query.eval(None, &self.changes)? {
+                emit(values);
+            }
+            return Ok(None);
+        }
+        let mut sorter = query
+            .order_by
+            .as_ref()
+            .map(|(_, descending)| Sorter::new(*descending));
+        let mut cursor = Cursor::from_start(self)?;
+        while !cursor.end_of_table {
+            if let Some(cell) = cursor.read_leaf_cell()?
+                && let Some(values) = query.eval(Some(&cell.value), &cursor.table.changes)?
+            {
+                match (&mut sorter, &query.order_by) {
+                    (Some(sorter), Some((key, _))) => {
+                        sorter.push(key.eval(Some(&cell.value), &cursor.table.changes)?, values)?
+                    }
+                    _ => emit(values),
+                }
+            }
+            cursor.advance()?;
+        }
+        drop(cursor);
+        if let Some(sorter) = sorter {
+            let sort_start = Instant::now();
+            sorter.finish(&mut emit)?;
+            self.sort_time = Some(sort_start.elapsed());
+        }
+        Ok(None)
+    }
+
+    // visits every row in key order without printing, returns the row count
+    fn scan(&mut self, mut visit: impl FnMut(&LeafCell)) -> Result<usize, Box<dyn Error>> {
+        let mut n_rows = 0;
+        let mut cursor = Cursor::from_start(self)?;
+        while !cursor.end_of_table {
+            if let Some(cell) = cursor.read_leaf_cell()? {
+                visit(&cell);
+                n_rows += 1;
+            }
+            cursor.advance()?;
+        }
+        Ok(n_rows)
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.pager.flush_dirty_pages()
+    }
+
+    // flushes for the last time and hands any failure to the caller
+    fn close(mut self) -> Result<(), Box<dyn Error>> {
+        self.closed = true;
+        self.flush()
+    }
+}
+
+// only a fallback for tables that were never closed, it can't report failure
+// to anyone so it just logs
+impl Drop for Table {
+    fn drop(&mut self) {
+        debug_assert!(self.pager.pin_count.iter().all(|&n| n == 0));
+        if self.closed {
+            return;
+        }
+        if let Err(error) = self.flush() {
+            eprintln!("ERROR: db close: {error}");
+        }
+    }
+}
+
+impl Pager {
+    fn new(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        Self::from_file(file)
+    }
+
+    // a pager over a file opened read-only fails on flush, so only pages
+    // that are never dirtied can be used through it
+    fn from_file(file: File) -> Result<Self, Box<dyn Error>> {
+        let file_size = file.metadata()?.len() as usize;
+        if !file_size.is_multiple_of(PAGE_SIZE) {
+            return Err(ERR_INVALID_FILE.into());
+        }
+        let n_pages = file_size / PAGE_SIZE;
+        if n_pages > PAGE_MAX_NUM {
+            return Err(format!("ERROR: database has {n_pages} pages, max {PAGE_MAX_NUM}.").into());
+        }
+        let mut pager = Pager {
+            file,
+            n_pages,
+            pages: [const { None }; PAGE_MAX_NUM],
+            dirty: [false; PAGE_MAX_NUM],
+            write_buf: Vec::new(),
+            read_buf: Vec::new(),
+            stats: PagerStats::default(),
+            pin_count: [0; PAGE_MAX_NUM],
+            synchronous: false,
+        };
+        if n_pages > 0 {
+            let header = Node::read_at(&pager.file, HEADER_PAGE_INDEX)?;
+            match header.format_version() {
+                Some(version) if version < FORMAT_VERSION => {
+                    return Err(format!(
+                        "ERROR: database format version {version} is older than {FORMAT_VERSION}, upgrade it with rqlite migrate."
+                    )
+                    .into());
+                }
+                Some(version) if version > FORMAT_VERSION => {
+                    return Err(format!(
+                        "ERROR: database format version {version} is newer than {FORMAT_VERSION}, use a newer rqlite."
+                    )
+                    .into());
+                }
+                _ => {}
+            }
+            if n_pages == 1 {
+                return Err(CorruptionError::new(ROOT_PAGE_INDEX, "root", "missing").into());
+            }
+            pager.read_page(HEADER_PAGE_INDEX)?;
+        }
+        Ok(pager)
+    }
+
+    // forgets every cached page so the next reads see what other processes
+    // wrote since; dirty pages have to be flushed first
+    fn reload(&mut self) -> Result<(), Box<dyn Error>> {
+        debug_assert!(!self.dirty.contains(&true) && self.pin_count.iter().all(|&n| n == 0));
+        let file_size = self.file.metadata()?.len() as usize;
+        if !file_size.is_multiple_of(PAGE_SIZE) {
+            return Err(ERR_INVALID_FILE.into());
+        }
+        let n_pages = file_size / PAGE_SIZE;
+        if n_pages > PAGE_MAX_NUM {
+            return Err(format!("ERROR: database has {n_pages} pages, max {PAGE_MAX_NUM}.").into());
+        }
+        self.n_pages = n_pages;
+        self.pages = [const { None }; PAGE_MAX_NUM];
+        Ok(())
+    }
+
+    // drops every page from n_pages on; the pages kept are flushed before the
+    // file shrinks, so a crash in between leaves only unreachable pages
+    fn truncate(&mut self, n_pages: usize) -> Result<(), Box<dyn Error>> {
+        self.drop_pages(n_pages);
+        self.flush_dirty_pages()?;
+        retry_io(|| self.file.set_len((n_pages * PAGE_SIZE) as u64))
+            .map_err(|error| self.write_error(error))?;
+        if self.synchronous {
+            retry_io(|| self.file.sync_data()).map_err(|error| self.write_error(error))?;
+        }
+        Ok(())
+    }
+
+    // forgets the pages from n_pages on, in the cache only
+    fn drop_pages(&mut self, n_pages: usize) {
+        debug_assert!(self.pin_count[n_pages..].iter().all(|&n| n == 0));
+        self.pages[n_pages..].fill_with(|| None);
+        self.dirty[n_pages..].fill(false);
+        self.n_pages = self.n_pages.min(n_pages);
+    }
+
+    fn print_tree(
+        &mut self,
+        page_index: usize,
+        indentation: usize,
+        visited: &mut Visited,
+    ) -> Result<(), Box<dyn Error>> {
+        visited.visit(page_index, "child")?;
+        self.read_page(page_index)?;
+        let (node_kind, n_cells) = {
+            let node = self.pages[page_index].as_ref().unwrap();
+            (node.kind(), node.get_n_cells())
+        };
+        match node_kind {
+            NodeKind::Leaf => {
+                print_with_indentation(indentation, format!("- leaf (size {n_cells})").as_ref());
+                for i in 0..n_cells {
+                    let key = {
+                        let node = self.pages[page_index].as_ref().unwrap();
+                        node.leaf_key(i)
+                    };
+                    print_with_indentation(indentation + 1, format!("- {}", key).as_ref());
+                }
+            }
+            NodeKind::Internal => {
+                print_with_indentation(
+                    indentation,
+                    format!("- internal (size {n_cells})").as_ref(),
+                );
+                for i in 0..n_cells {
+                    let (child_page, key) = {
+                        let node = self.pages[page_index].as_ref().unwrap();
+                        (node.internal_child(i) as usize, node.internal_key(i))
+                    };
+                    self.print_tree(child_page, indentation + 1, visited)?;
+                    print_with_indentation(indentation + 1, format!("- key {}", key).as_ref());
+                }
+                let right_child = {
+                    let node = self.pages[page_index].as_ref().unwrap();
+                    node.right_child() as usize
+                };
+                self.print_tree(right_child, indentation + 1, visited)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_new_page_index(&self) -> usize {
+        self.n_pages
+    }
+
+    // loads the page if needed and keeps it resident until the handle is unpinned
+    fn pin(&mut self, page_index: usize) -> Result<PageHandle, Box<dyn Error>> {
+        self.read_page(page_index)?;
+        self.pin_count[page_index] += 1;
+        Ok(PageHandle { page_index })
+    }
+
+    fn unpin(&mut self, page: &PageHandle) {
+        self.pin_count[page.page_index] -= 1;
+    }
+
+    // a pinned page is always resident, so the unwraps below never fire
+    fn node(&self, page: &PageHandle) -> &Node {
+        self.pages[page.page_index].as_deref().unwrap()
+    }
+
+    fn node_mut(&mut self, page: &PageHandle) -> &mut Node {
+        self.dirty[page.page_index] = true;
+        self.pages[page.page_index].as_deref_mut().unwrap()
+    }
+
+    // the two handles must be different pages
+    fn two_nodes_mut(&mut self, first: &PageHandle, second: &PageHandle) -> (&mut Node, &mut Node) {
+        self.dirty[first.page_index] = true;
+        self.dirty[second.page_index] = true;
+        let [first, second] = self
+            .pages
+            .get_disjoint_mut([first.page_index, second.page_index])
+            .unwrap()
+            .map(|page| page.as_deref_mut().unwrap());
+        (first, second)
+    }
+
+    // for reading only, the page is not marked dirty
+    fn read_page(&mut self, page_index: usize) -> Result<&Node, Box<dyn Error>> {
+        if page_index >= self.n_pages {
+            return Err(format!("ERROR: page {page_index} not exist.").into());
+        }
+        self.load_page(page_index)?;
+        Ok(self.pages[page_index].as_deref().unwrap())
+    }
+
+    fn savepoint(&self) -> Savepoint {
+        let copy = |node: &Node| {
+            let mut copy = Node::new();
+            copy.data = node.data;
+            copy
+        };
+        Savepoint {
+            n_pages: self.n_pages,
+            pages: self
+                .pages
+                .iter()
+                .map(|page| page.as_deref().map(copy))
+                .collect(),
+            dirty: self.dirty,
+        }
+    }
+
+    // pages first read after the savepoint are still as on disk, so putting
+    // back the cached ones and the dirty flags undoes every change since
+    fn rollback(&mut self, savepoint: Savepoint) {
+        self.n_pages = savepoint.n_pages;
+        for (page, saved) in self.pages.iter_mut().zip(savepoint.pages) {
+            *page = saved;
+        }
+        self.dirty = savepoint.dirty;
+    }
+
+    fn get_page(&mut self, page_index: usize) -> Result<&mut Node, Box<dyn Error>> {
+        if page_index >= PAGE_MAX_NUM {
+            return Err(ERR_TABLE_FULL.into());
+        }
+        self.dirty[page_index] = true;
+        if page_index < self.n_pages {
+            self.load_page(page_index)?;
+        } else {
+            self.n_pages = page_index + 1;
+            self.pages[page_index] = Some(Node::new());
+        }
+        Ok(self.pages[page_index].as_mut().unwrap())
+    }
+
+    fn load_page(&mut self, page_index: usize) -> Result<(), Box<dyn Error>> {
+        if self.pages[page_index].is_some() {
+            self.stats.cache_hits += 1;
+        } else {
+            self.stats.cache_misses += 1;
+        }
+        self.fetch_page_from_file(page_index)
+    }
+
+    fn fetch_page_from_file(&mut self, page_index: usize) -> Result<(), Box<dyn Error>> {
+        if self.pages[page_index].is_none() {
+            let start = Instant::now();
+            let node = Node::read_at(&self.file, page_index * PAGE_SIZE);
+            self.stats.io_time += start.elapsed();
+            let node = node?;
+            node.validate(page_index, self.n_pages)?;
+            self.pages[page_index] = Some(node);
+            self.stats.pages_read += 1;
+            self.stats.read_calls += 1;
+        }
+        Ok(())
+    }
+
+    // loads the run of uncached pages starting at page_index with one read.
+    // only a hint: anything that fails here is left for read_page to report
+    fn prefetch(&mut self, page_index: usize) {
+        let end = (page_index + READAHEAD_PAGES).min(self.n_pages);
+        let n_pages = self.pages[page_index.min(end)..end]
+            .iter()
+            .take_while(|page| page.is_none())
+            .count();
+        if n_pages == 0 {
+            return;
+        }
+        self.read_buf.resize(n_pages * PAGE_SIZE, 0);
+        let start = Instant::now();
+        let read = self
+            .file
+            .read_exact_at(&mut self.read_buf, (page_index * PAGE_SIZE) as u64);
+        self.stats.io_time += start.elapsed();
+        if read.is_err() {
+            return;
+        }
+        self.stats.read_calls += 1;
+        for (i, data) in self.read_buf.chunks(PAGE_SIZE).enumerate() {
+            let mut node = Node::new();
+            node.data.copy_from_slice(data);
+            if node.validate(page_index + i, self.n_pages).is_err() {
+                break;
+            }
+            self.pages[page_index + i] = Some(node);
+            self.stats.pages_read += 1;
+        }
+    }
+
+    // a failed write as an error, disk full naming the pages left unflushed
+    fn write_error(&self, error: io::Error) -> Box<dyn Error> {
+        match error.kind() {
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => {
+                let n_dirty = self.dirty.iter().filter(|&&dirty| dirty).count();
+                format!("ERROR: disk full, {n_dirty} pages unflushed.").into()
+            }
+            _ => error.into(),
+        }
+    }
+
+    fn flush_dirty_pages(&mut self) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        let result = self.write_dirty_pages();
+        self.stats.io_time += start.elapsed();
+        result
+    }
+
+    // writes dirty pages in page order, a run of adjacent dirty pages is
+    // gathered into write_buf and goes out as a single write
+    // a page stays dirty until its write succeeds, so after a failure the
+    // flush can simply be run again
+    fn write_dirty_pages(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut page_index = 0;
+        let mut wrote = false;
+        while page_index < self.n_pages {
+            if !self.dirty[page_index] {
+                page_index += 1;
+                continue;
+            }
+            let start = page_index;
+            while page_index < self.n_pages && self.dirty[page_index] {
+                page_index += 1;
+            }
+            let run = &self.pages[start..page_index];
+            if run.len() == 1 {
+                let node = run[0].as_ref().unwrap();
+                retry_io(|| node.write_at(&self.file, start * PAGE_SIZE))
+                    .map_err(|error| self.write_error(error))?;
+            } else {
+                self.write_buf.clear();
+                for page in run {
+                    self.write_buf
+                        .extend_from_slice(&page.as_ref().unwrap().data);
+                }
+                retry_io(|| {
+                    self.file
+                        .write_all_at(&self.write_buf, (start * PAGE_SIZE) as u64)
+                })
+                .map_err(|error| self.write_error(error))?;
+            }
+            self.dirty[start..page_index].fill(false);
+            self.stats.pages_written += page_index - start;
+            self.stats.write_calls += 1;
+            wrote = true;
+        }
+        if wrote && self.synchronous {
+            retry_io(|| self.file.sync_data()).map_err(|error| self.write_error(error))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Cursor<'a> {
+    fn from(table: &'a mut Table, key: i64) -> Result<Self, Box<dyn Error>> {
+        let (page_index, _) = table.find_leaf(key)?;
+        Self::from_leaf_node(table, page_index, key)
+    }
+
+    fn from_start(table: &'a mut Table) -> Result<Self, Box<dyn Error>> {
+        Self::from(table, 0)
+    }
+
+    fn from_leaf_node(
+        table: &'a mut Table,
+        page_index: usize,
+        key: i64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let page = table.pager.pin(page_index)?;
+        let node = table.pager.node(&page);
+        let n_cells = node.get_n_cells();
+        let mut left = 0usize;
+        let mut right = n_cells;
+        while left != right {
+            let mid = (left + right) / 2;
+            let cell_key = node.leaf_key(mid);
+            if key == cell_key {
+                return Ok(Cursor {
+                    table,
+                    page,
+                    cell_index: mid,
+                    end_of_table: false,
+                    visited: Visited::new(),
+                });
+            } else if key < cell_key {
+                right = mid;
+            } else {
+                left = mid + 1;
+            }
+        }
+        Ok(Cursor {
+            table,
+            page,
+            cell_index: left,
+            end_of_table: key == 0 && n_cells == 0,
+            visited: Visited::new(),
+        })
+    }
+
+    fn advance(&mut self) -> Result<(), Box<dyn Error>> {
+        self.cell_index += 1;
+        self.table.rows_examined += 1;
+        let node = self.table.pager.node(&self.page);
+        let end_of_cell = self.cell_index >= node.get_n_cells();
+        if end_of_cell {
+            let next_leaf = node.next_leaf();
+            if next_leaf != NOT_EXIST {
+                self.table.check_timeout()?;
+                // the leaf the cursor started on counts as reached too
+                self.visited.0[self.page.page_index] = true;
+                self.visited.visit(next_leaf as usize, "next_leaf")?;
+                let next_page = self.table.pager.pin(next_leaf as usize)?;
+                self.table.pager.unpin(&self.page);
+                self.page = next_page;
+                self.cell_index = 0;
+                // a scan that crossed into this leaf will most likely want the
+                // leaves after it too
+                let next_leaf = self.table.pager.node(&self.page).next_leaf();
+                if next_leaf != NOT_EXIST {
+                    self.table.pager.prefetch(next_leaf as usize);
+                }
+            } else {
+                self.end_of_table = true;
+            }
+        }
+        Ok(())
+    }
+
+    // a cursor back at a bookmarked cell, as long as that cell still holds
+    // the bookmarked key
+    fn resume(table: &'a mut Table, bookmark: &Bookmark) -> Result<Self, Box<dyn Error>> {
+        let Bookmark {
+            page_index,
+            cell_index,
+            key,
+        } = *bookmark;
+        if page_index == HEADER_PAGE_INDEX || page_index >= table.pager.n_pages {
+            return Err(ERR_BOOKMARK_STALE.into());
+        }
+        let node = table.pager.read_page(page_index)?;
+        if !matches!(node.kind(), NodeKind::Leaf)
+            || cell_index >= node.get_n_cells()
+            || node.leaf_key(cell_index) != key
+        {
+            return Err(ERR_BOOKMARK_STALE.into());
+        }
+        let page = table.pager.pin(page_index)?;
+        Ok(Cursor {
+            table,
+            page,
+            cell_index,
+            end_of_table: false,
+            visited: Visited::new(),
+        })
+    }
+
+    // the rows from here on, at most limit of them, and a bookmark of the
+    // next row when the limit stopped the scan short of the end
+    fn take(mut self, limit: Option<usize>) -> Result<RowBatch, Box<dyn Error>> {
+        let mut rows = Vec::new();
+        while !self.end_of_table && limit.is_none_or(|limit| rows.len() < limit) {
+            if let Some(cell) = self.read_leaf_cell()? {
+                rows.push(cell.value);
+            }
+            self.advance()?;
+        }
+        // a seek can stop past the last cell of a leaf, the next row being
+        // in the leaf after it
+        while !self.end_of_table && self.read_leaf_cell()?.is_none() {
+            self.advance()?;
+        }
+        let bookmark = match self.read_leaf_cell()? {
+            Some(cell) if !self.end_of_table => Some(Bookmark {
+                page_index: self.page.page_index,
+                cell_index: self.cell_index,
+                key: cell.key,
+            }),
+            _ => None,
+        };
+        Ok((rows, bookmark))
+    }
+
+    fn read_leaf_cell(&self) -> Result<Option<LeafCell>, Box<dyn Error>> {
+        Ok(self
             .table
             .pager
-            .get_page(self.page_index)?
+            .node(&self.page)
             .read_leaf_cell(self.cell_index))
     }
 
-    fn write_leaf_cell(&mut self, cell: LeafCell) -> Result<(), Box<dyn Error>> {
-        let node = self.table.pager.get_page(self.page_index)?;
-        if node.get_n_cells() < LEAF_NODE_CELL_MAX_NUM {
-            node.insert_leaf_cell(self.cell_index, cell);
-            return Ok(());
+    // how many of the full leaf's cells plus the new one stay in it. appending
+    // past the last leaf or prepending before the first is what a sequential
+    // load looks like, so the leaf left behind is filled to leaf_fill_percent
+    // and the growing edge gets the rest; anything else splits in half
+    fn split_point(
+        &mut self,
+        page_index: usize,
+        is_last_leaf: bool,
+    ) -> Result<usize, Box<dyn Error>> {
+        let edge_n_cells = ((LEAF_NODE_CELL_MAX_NUM + 1) * self.table.leaf_fill_percent / 100)
+            .clamp(1, LEAF_NODE_CELL_MAX_NUM);
+        if is_last_leaf && self.cell_index == LEAF_NODE_CELL_MAX_NUM {
+            return Ok(edge_n_cells);
+        }
+        if self.cell_index == 0 && self.table.find_leaf(i64::MIN)?.0 == page_index {
+            return Ok(LEAF_NODE_CELL_MAX_NUM + 1 - edge_n_cells);
+        }
+        Ok(SPLIT_LEFT_LEAF_NODE_NUM)
+    }
+
+    fn write_leaf_cell(&mut self, cell: LeafCell) -> Result<(), Box<dyn Error>> {
+        let page_index = self.page.page_index;
+        let node = self.table.pager.node_mut(&self.page);
+        if node.get_n_cells() < LEAF_NODE_CELL_MAX_NUM {
+            node.insert_leaf_cell(self.cell_index, cell);
+            return self.table.add_rows(1);
+        }
+        let is_last_leaf = node.next_leaf() == NOT_EXIST;
+        let left_n_cells = self.split_point(page_index, is_last_leaf)?;
+        let node = self.table.pager.node(&self.page);
+        let is_root = node.is_root();
+        let first_key = node.leaf_key(0);
+        let new_page_index = self.table.pager.get_new_page_index();
+        let needed_pages = if is_root { 2 } else { 1 };
+        if new_page_index + needed_pages > PAGE_MAX_NUM {
+            return Err(ERR_TABLE_FULL.into());
+        }
+        let parent_index = if is_root {
+            None
+        } else {
+            Some(self.table.find_parent(page_index, first_key)?)
+        };
+        if let Some(parent_index) = parent_index
+            && self.table.pager.read_page(parent_index)?.get_n_cells() >= INTERNAL_NODE_CELL_MAX_NUM
+        {
+            return Err(ERR_INTERNAL_NODE_FULL.into());
+        }
+        self.table
+            .pager
+            .get_page(new_page_index)?
+            .become_leaf_node();
+        let new_page = self.table.pager.pin(new_page_index)?;
+        let (old_node, new_node) = self.table.pager.two_nodes_mut(&self.page, &new_page);
+        new_node.set_next_leaf(old_node.next_leaf());
+        old_node.set_next_leaf(new_page_index as i32);
+        let mut cells = Vec::with_capacity((LEAF_NODE_CELL_MAX_NUM + 1) * LEAF_NODE_CELL_SIZE);
+        for i in 0..LEAF_NODE_CELL_MAX_NUM {
+            if i == self.cell_index {
+                cells.extend_from_slice(&cell.encode());
+            }
+            cells.extend_from_slice(old_node.leaf_cell_bytes(i));
+        }
+        if self.cell_index == LEAF_NODE_CELL_MAX_NUM {
+            cells.extend_from_slice(&cell.encode());
+        }
+        // both halves are rebuilt packed, slot i pointing at cell i
+        old_node.set_n_cells(0);
+        for (i, cell) in cells.chunks(LEAF_NODE_CELL_SIZE).enumerate() {
+            if i < left_n_cells {
+                old_node.push_leaf_cell(cell);
+            } else {
+                new_node.push_leaf_cell(cell);
+            }
+        }
+        self.table.rightmost_leaf_index = None;
+        if let Some(parent_index) = parent_index {
+            let left_max_key = old_node.get_max_key();
+            self.table.pager.unpin(&new_page);
+            let parent = self.table.pager.get_page(parent_index)?;
+            parent.insert_split_child(page_index, left_max_key, new_page_index);
+        } else {
+            self.table.pager.unpin(&new_page);
+            let left_child_page_index = self.table.pager.get_new_page_index();
+            self.table
+                .pager
+                .get_page(left_child_page_index)?
+                .become_leaf_node();
+            let left_page = self.table.pager.pin(left_child_page_index)?;
+            let (root_node, left_child) = self.table.pager.two_nodes_mut(&self.page, &left_page);
+            let n_cells = root_node.get_n_cells();
+            left_child.set_n_cells(n_cells);
+            left_child.set_next_leaf(root_node.next_leaf());
+            left_child.data[LEAF_NODE_SLOTS_OFFSET..]
+                .copy_from_slice(&root_node.data[LEAF_NODE_SLOTS_OFFSET..]);
+            root_node.become_internal_node();
+            root_node.set_n_cells(1);
+            root_node.set_right_child(new_page_index as i32);
+            root_node.put_internal_cell(0, left_child_page_index as i32, left_child.get_max_key());
+            self.table.pager.unpin(&left_page);
+        }
+        self.table.add_rows(1)
+    }
+}
+
+impl Drop for Cursor<'_> {
+    fn drop(&mut self) {
+        self.table.pager.unpin(&self.page);
+    }
+}
+
+impl Row {
+    fn parse(args: &[&str]) -> Result<Self, Box<dyn Error>> {
+        // TODO: parse ""
+        let args = args
+            .iter()
+            .filter(|str| !str.is_empty())
+            .collect::<Vec<_>>(); // filter out the internal space
+        if args.len() != 3 {
+            return Err(ERR_INSERT_SYNTAX.into());
+        }
+        let id = args[0].parse::<i64>().map_err(|_| ERR_INSERT_SYNTAX)?;
+        Self::new(id, args[1], args[2])
+    }
+
+    fn new(id: i64, name: &str, description: &str) -> Result<Self, Box<dyn Error>> {
+        if id <= 0 {
+            return Err(ERR_NOT_POSITIVE_ID.into());
+        }
+        check_text_len("name", name, NAME_MAX_SIZE)?;
+        check_text_len("description", description, DESCRIPTION_MAX_SIZE)?;
+        // the fields are NUL padded on disk, a NUL inside one would cut it short
+        if name.contains('\0') || description.contains('\0') {
+            return Err(ERR_TEXT_NUL.into());
+        }
+        // whole fields are copied, so a multi-byte character is never split
+        let mut name_buf = [0u8; NAME_MAX_SIZE];
+        let mut description_buf = [0u8; DESCRIPTION_MAX_SIZE];
+        name_buf[..name.len()].copy_from_slice(name.as_bytes());
+        description_buf[..description.len()].copy_from_slice(description.as_bytes());
+        Ok(Row {
+            id,
+            name: name_buf,
+            description: description_buf,
+        })
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        decode_text(&self.name)
+    }
+
+    fn description(&self) -> Cow<'_, str> {
+        decode_text(&self.description)
+    }
+}
+
+impl LeafCell {
+    fn encode(&self) -> [u8; LEAF_NODE_CELL_SIZE] {
+        let mut buf = [0u8; LEAF_NODE_CELL_SIZE];
+        let mut write = |offset: usize, bytes: &[u8]| {
+            buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        write(LEAF_NODE_CELL_KEY_OFFSET, &self.key.to_le_bytes());
+        write(LEAF_NODE_CELL_ID_OFFSET, &self.value.id.to_le_bytes());
+        write(LEAF_NODE_CELL_NAME_OFFSET, &self.value.name);
+        write(LEAF_NODE_CELL_DESCRIPTION_OFFSET, &self.value.description);
+        buf
+    }
+    fn decode(buf: &[u8]) -> Self {
+        let field = |offset: usize, size: usize| &buf[offset..offset + size];
+        LeafCell {
+            key: i64::from_le_bytes(
+                field(LEAF_NODE_CELL_KEY_OFFSET, LEAF_NODE_CELL_KEY_SIZE)
+                    .try_into()
+                    .unwrap(),
+            ),
+            value: Row {
+                id: i64::from_le_bytes(
+                    field(LEAF_NODE_CELL_ID_OFFSET, ID_SIZE).try_into().unwrap(),
+                ),
+                name: field(LEAF_NODE_CELL_NAME_OFFSET, NAME_MAX_SIZE)
+                    .try_into()
+                    .unwrap(),
+                description: field(LEAF_NODE_CELL_DESCRIPTION_OFFSET, DESCRIPTION_MAX_SIZE)
+                    .try_into()
+                    .unwrap(),
+            },
+        }
+    }
+}
+
+impl From<&Row> for LeafCell {
+    fn from(row: &Row) -> Self {
+        LeafCell {
+            key: row.id,
+            value: row.clone(),
+        }
+    }
+}
+
+impl Changes {
+    fn record(&mut self, last_id: i64, n_rows: usize) {
+        self.last_insert_rowid = last_id;
+        self.changes = n_rows;
+        self.total_changes += n_rows;
+    }
+}
+
+impl CorruptionError {
+    fn new(page_index: usize, field: &'static str, detail: impl Into<String>) -> Self {
+        CorruptionError {
+            page_index,
+            field,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl Visited {
+    fn new() -> Self {
+        Visited([false; PAGE_MAX_NUM])
+    }
+    // `field` is the pointer that led to the page, child or next_leaf
+    fn visit(&mut self, page_index: usize, field: &'static str) -> Result<(), CorruptionError> {
+        if !mem::replace(&mut self.0[page_index], true) {
+            return Ok(());
+        }
+        let walk = if field == "next_leaf" {
+            "leaf chain"
+        } else {
+            "tree"
+        };
+        let detail = format!("reached twice, the {walk} has a cycle");
+        Err(CorruptionError::new(page_index, field, detail))
+    }
+}
+
+impl fmt::Display for CorruptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ERROR: database corrupt, page {} {}: {}.",
+            self.page_index, self.field, self.detail
+        )
+    }
+}
+
+impl Error for CorruptionError {}
+
+impl SyntaxError {
+    fn at(offset: usize, message: String) -> Self {
+        SyntaxError {
+            message,
+            offset: Some(offset),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for SyntaxError {}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}, {}, {}]", self.id, self.name(), self.description())
+    }
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{USAGE}")
+    }
+}
+
+impl Error for UsageError {}
+
+impl fmt::Display for DuplicateKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ERROR: key '{}' already exist.", self.0)
+    }
+}
+
+impl Error for DuplicateKeyError {}
+
+impl NodeKind {
+    fn from_u8(v: u8) -> Result<Self, Box<dyn Error>> {
+        match v {
+            1 => Ok(Self::Internal),
+            2 => Ok(Self::Leaf),
+            _ => Err("ERROR: unkown value {v}, can't transform valid node kind.".into()),
+        }
+    }
+    fn to_u8(&self) -> u8 {
+        match self {
+            Self::Internal => 1,
+            Self::Leaf => 2,
+        }
+    }
+}
+
+impl Node {
+    fn new() -> Box<Self> {
+        let mut node = Box::new(Node {
+            data: [0u8; PAGE_SIZE],
+        });
+        node.set_kind(NodeKind::Leaf);
+        node
+    }
+    fn become_header_page(&mut self) {
+        self.data.fill(0);
+        self.write_bytes(HEADER_MAGIC_OFFSET, HEADER_MAGIC);
+        self.write_bytes(HEADER_VERSION_OFFSET, &FORMAT_VERSION.to_le_bytes());
+    }
+    fn n_rows(&self) -> usize {
+        u64::from_le_bytes(self.read_bytes(HEADER_N_ROWS_OFFSET)) as usize
+    }
+    fn set_n_rows(&mut self, n_rows: usize) {
+        self.write_bytes(HEADER_N_ROWS_OFFSET, &(n_rows as u64).to_le_bytes());
+    }
+    fn sequence(&self, slot: usize) -> Option<(String, i64)> {
+        let offset = HEADER_SEQUENCES_OFFSET + slot * SEQUENCE_SLOT_SIZE;
+        let name = self.read_bytes::<SEQUENCE_NAME_MAX_SIZE>(offset);
+        if name[0] == 0 {
+            return None;
+        }
+        let next = i64::from_le_bytes(self.read_bytes(offset + SEQUENCE_NAME_MAX_SIZE));
+        Some((decode_text(&name).into_owned(), next))
+    }
+    fn set_sequence(&mut self, slot: usize, name: &str, next: i64) {
+        let offset = HEADER_SEQUENCES_OFFSET + slot * SEQUENCE_SLOT_SIZE;
+        let mut name_buf = [0u8; SEQUENCE_NAME_MAX_SIZE];
+        name_buf[..name.len()].copy_from_slice(name.as_bytes());
+        self.write_bytes(offset, &name_buf);
+        self.write_bytes(offset + SEQUENCE_NAME_MAX_SIZE, &next.to_le_bytes());
+    }
+    // None when page 0 is neither a header nor the root of a format 0 file
+    fn format_version(&self) -> Option<u32> {
+        if self.read_bytes::<{ HEADER_MAGIC.len() }>(HEADER_MAGIC_OFFSET) == *HEADER_MAGIC {
+            let version = u32::from_le_bytes(self.read_bytes(HEADER_VERSION_OFFSET));
+            return Some(version.max(1));
+        }
+        let is_root_node = NodeKind::from_u8(self.data[NODE_KIND_OFFSET]).is_ok() && self.is_root();
+        is_root_node.then_some(0)
+    }
+    // moves everything after is_root up over the parent field of versions
+    // before 4
+    fn drop_parent_field(&mut self) {
+        self.data.copy_within(
+            NODE_N_CELLS_OFFSET + OLD_NODE_PARENT_SIZE..,
+            NODE_N_CELLS_OFFSET,
+        );
+        self.data[PAGE_SIZE - OLD_NODE_PARENT_SIZE..].fill(0);
+    }
+    // adds `delta` to every page index the node holds
+    fn shift_page_indexes(&mut self, delta: i32) {
+        let shift = |index: i32| {
+            if index == NOT_EXIST {
+                index
+            } else {
+                index + delta
+            }
+        };
+        match self.kind() {
+            NodeKind::Leaf => self.set_next_leaf(shift(self.next_leaf())),
+            NodeKind::Internal => {
+                for i in 0..self.get_n_cells() {
+                    let (child, key) = (self.internal_child(i), self.internal_key(i));
+                    self.put_internal_cell(i, shift(child), key);
+                }
+                self.set_right_child(shift(self.right_child()));
+            }
+        }
+    }
+    fn become_leaf_node(&mut self) {
+        self.set_kind(NodeKind::Leaf);
+        self.set_n_cells(0);
+        self.set_next_leaf(NOT_EXIST);
+    }
+    fn become_internal_node(&mut self) {
+        self.set_kind(NodeKind::Internal);
+        self.set_n_cells(0);
+        self.set_right_child(NOT_EXIST);
+    }
+    fn read_bytes<const N: usize>(&self, offset: usize) -> [u8; N] {
+        self.data[offset..offset + N].try_into().unwrap()
+    }
+    fn write_bytes(&mut self, offset: usize, bytes: &[u8]) {
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+    fn kind(&self) -> NodeKind {
+        // the kind byte is validated when the page is loaded
+        NodeKind::from_u8(self.data[NODE_KIND_OFFSET]).unwrap()
+    }
+    fn set_kind(&mut self, kind: NodeKind) {
+        self.data[NODE_KIND_OFFSET] = kind.to_u8();
+    }
+    fn is_root(&self) -> bool {
+        self.data[NODE_IS_ROOT_OFFSET] != 0
+    }
+    fn set_root(&mut self, is_root: bool) {
+        self.data[NODE_IS_ROOT_OFFSET] = is_root as u8;
+    }
+    fn get_n_cells(&self) -> usize {
+        u32::from_le_bytes(self.read_bytes(NODE_N_CELLS_OFFSET)) as usize
+    }
+    fn set_n_cells(&mut self, n_cells: usize) {
+        self.write_bytes(NODE_N_CELLS_OFFSET, &(n_cells as u32).to_le_bytes());
+    }
+    fn next_leaf(&self) -> i32 {
+        i32::from_le_bytes(self.read_bytes(LEAF_NODE_NEXT_LEAF_OFFSET))
+    }
+    fn set_next_leaf(&mut self, next_leaf: i32) {
+        self.write_bytes(LEAF_NODE_NEXT_LEAF_OFFSET, &next_leaf.to_le_bytes());
+    }
+    fn right_child(&self) -> i32 {
+        i32::from_le_bytes(self.read_bytes(INTERNAL_NODE_RIGHT_CHILD_OFFSET))
+    }
+    fn set_right_child(&mut self, right_child: i32) {
+        self.write_bytes(INTERNAL_NODE_RIGHT_CHILD_OFFSET, &right_child.to_le_bytes());
+    }
+    fn leaf_slot(&self, cell_index: usize) -> usize {
+        let offset = LEAF_NODE_SLOTS_OFFSET + cell_index * LEAF_NODE_SLOT_SIZE;
+        u16::from_le_bytes(self.read_bytes(offset)) as usize
+    }
+    fn set_leaf_slot(&mut self, cell_index: usize, slot: usize) {
+        let offset = LEAF_NODE_SLOTS_OFFSET + cell_index * LEAF_NODE_SLOT_SIZE;
+        self.write_bytes(offset, &(slot as u16).to_le_bytes());
+    }
+    fn leaf_cell_range(&self, cell_index: usize) -> Range<usize> {
+        let start = LEAF_NODE_CELLS_OFFSET + self.leaf_slot(cell_index) * LEAF_NODE_CELL_SIZE;
+        start..start + LEAF_NODE_CELL_SIZE
+    }
+    fn leaf_cell_bytes(&self, cell_index: usize) -> &[u8] {
+        &self.data[self.leaf_cell_range(cell_index)]
+    }
+    fn internal_cell_offset(&self, cell_index: usize) -> usize {
+        INTERNAL_NODE_HEADER_SIZE + cell_index * INTERNAL_NODE_CELL_SIZE
+    }
+    // index of the first cell whose key is not less than `key`, which is the
+    // child that key belongs to (n_cells meaning the right child)
+    fn find_internal_cell(&self, key: i64) -> usize {
+        let mut left = 0usize;
+        let mut right = self.get_n_cells();
+        while left != right {
+            let mid = (left + right) / 2;
+            if key <= self.internal_key(mid) {
+                right = mid;
+            } else {
+                left = mid + 1;
+            }
+        }
+        left
+    }
+    // index of the leaf cell holding exactly `key`
+    fn find_leaf_key(&self, key: i64) -> Option<usize> {
+        let mut left = 0usize;
+        let mut right = self.get_n_cells();
+        while left != right {
+            let mid = (left + right) / 2;
+            let cell_key = self.leaf_key(mid);
+            if key == cell_key {
+                return Some(mid);
+            } else if key < cell_key {
+                right = mid;
+            } else {
+                left = mid + 1;
+            }
+        }
+        None
+    }
+    fn leaf_key(&self, cell_index: usize) -> i64 {
+        let offset = self.leaf_cell_range(cell_index).start + LEAF_NODE_CELL_KEY_OFFSET;
+        i64::from_le_bytes(self.read_bytes(offset))
+    }
+    fn internal_key(&self, cell_index: usize) -> i64 {
+        let offset = self.internal_cell_offset(cell_index) + INTERNAL_NODE_CELL_KEY_OFFSET;
+        i64::from_le_bytes(self.read_bytes(offset))
+    }
+    fn internal_child(&self, cell_index: usize) -> i32 {
+        let offset = self.internal_cell_offset(cell_index) + INTERNAL_NODE_CELL_CHILD_OFFSET;
+        i32::from_le_bytes(self.read_bytes(offset))
+    }
+    // the leaf cells decoded from where `start` says cell i begins, or None
+    // when they don't read as rows: keys ascending and each equal to its id
+    fn cells_at(&self, start: impl Fn(usize) -> Option<usize>) -> Option<Vec<LeafCell>> {
+        let n_cells = self.get_n_cells();
+        if n_cells > LEAF_NODE_CELL_MAX_NUM {
+            return None;
+        }
+        let cells = (0..n_cells)
+            .map(|i| {
+                Some(LeafCell::decode(
+                    &self.data[start(i)?..][..LEAF_NODE_CELL_SIZE],
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let is_rows = cells.iter().all(|cell| cell.key == cell.value.id)
+            && cells.windows(2).all(|pair| pair[0].key < pair[1].key);
+        is_rows.then_some(cells)
+    }
+    // checks what the accessors rely on, so a damaged page is reported when it
+    // is loaded rather than panicking later; pointers that loop through other
+    // pages are caught by the walks, see Visited
+    fn validate(&self, page_index: usize, n_pages: usize) -> Result<(), CorruptionError> {
+        let corrupt = |field, detail: String| Err(CorruptionError::new(page_index, field, detail));
+        if page_index == HEADER_PAGE_INDEX {
+            if self.read_bytes::<{ HEADER_MAGIC.len() }>(HEADER_MAGIC_OFFSET) != *HEADER_MAGIC {
+                return corrupt("magic", "not an rqlite database".to_string());
+            }
+            return Ok(());
+        }
+        let is_tree_page = |index: i32| {
+            index as usize != HEADER_PAGE_INDEX
+                && index as usize != page_index
+                && (index as usize) < n_pages
+        };
+        let kind = self.data[NODE_KIND_OFFSET];
+        let Ok(kind) = NodeKind::from_u8(kind) else {
+            return corrupt("kind", format!("unknown node kind {kind}"));
+        };
+        let n_cells = self.get_n_cells();
+        match kind {
+            NodeKind::Leaf => {
+                if n_cells > LEAF_NODE_CELL_MAX_NUM {
+                    return corrupt(
+                        "n_cells",
+                        format!("{n_cells} over max {LEAF_NODE_CELL_MAX_NUM}"),
+                    );
+                }
+                if let Some(i) = (0..n_cells).find(|&i| self.leaf_slot(i) >= n_cells) {
+                    return corrupt("slot", format!("slot {i} points past {n_cells} cells"));
+                }
+                let next_leaf = self.next_leaf();
+                if next_leaf != NOT_EXIST && !is_tree_page(next_leaf) {
+                    return corrupt("next_leaf", format!("page {next_leaf} out of range"));
+                }
+            }
+            NodeKind::Internal => {
+                if n_cells > INTERNAL_NODE_CELL_MAX_NUM {
+                    return corrupt(
+                        "n_cells",
+                        format!("{n_cells} over max {INTERNAL_NODE_CELL_MAX_NUM}"),
+                    );
+                }
+                if let Some(child) = (0..n_cells)
+                    .map(|i| self.internal_child(i))
+                    .chain([self.right_child()])
+                    .find(|&child| !is_tree_page(child))
+                {
+                    return corrupt("child", format!("page {child} out of range"));
+                }
+            }
+        }
+        Ok(())
+    }
+    fn read_at(file: &File, offset: usize) -> Result<Box<Self>, Box<dyn Error>> {
+        let mut node = Node::new();
+        file.read_exact_at(&mut node.data, offset as u64)?;
+        Ok(node)
+    }
+    fn read_stats(&self) -> TableStats {
+        let mut stats = TableStats {
+            n_rows: u64::from_le_bytes(self.read_bytes(STATS_N_ROWS_OFFSET)) as usize,
+            n_leaves: u32::from_le_bytes(self.read_bytes(STATS_N_LEAVES_OFFSET)) as usize,
+            leaf_fill_percent: u32::from_le_bytes(self.read_bytes(STATS_LEAF_FILL_PERCENT_OFFSET))
+                as usize,
+            histogram: [0; STATS_HISTOGRAM_BUCKETS + 1],
+        };
+        for (i, key) in stats.histogram.iter_mut().enumerate() {
+            *key = i64::from_le_bytes(self.read_bytes(STATS_HISTOGRAM_OFFSET + i * ID_SIZE));
+        }
+        stats
+    }
+    fn write_stats(&mut self, stats: &TableStats) {
+        self.write_bytes(STATS_N_ROWS_OFFSET, &(stats.n_rows as u64).to_le_bytes());
+        self.write_bytes(
+            STATS_N_LEAVES_OFFSET,
+            &(stats.n_leaves as u32).to_le_bytes(),
+        );
+        self.write_bytes(
+            STATS_LEAF_FILL_PERCENT_OFFSET,
+            &(stats.leaf_fill_percent as u32).to_le_bytes(),
+        );
+        for (i, key) in stats.histogram.iter().enumerate() {
+            self.write_bytes(STATS_HISTOGRAM_OFFSET + i * ID_SIZE, &key.to_le_bytes());
+        }
+    }
+    fn write_at(&self, file: &File, offset: usize) -> io::Result<()> {
+        file.write_all_at(&self.data, offset as u64)
+    }
+    fn read_leaf_cell(&self, cell_index: usize) -> Option<LeafCell> {
+        if cell_index >= self.get_n_cells() {
+            return None;
+        }
+        Some(LeafCell::decode(self.leaf_cell_bytes(cell_index)))
+    }
+    // cells are never removed, so the cell area stays packed and the next
+    // free position is always n_cells
+    fn push_leaf_cell(&mut self, cell: &[u8]) {
+        let n_cells = self.get_n_cells();
+        self.set_leaf_slot(n_cells, n_cells);
+        let start = LEAF_NODE_CELLS_OFFSET + n_cells * LEAF_NODE_CELL_SIZE;
+        self.write_bytes(start, cell);
+        self.set_n_cells(n_cells + 1);
+    }
+    fn put_internal_cell(&mut self, cell_index: usize, child: i32, key: i64) {
+        debug_assert!(cell_index < INTERNAL_NODE_CELL_MAX_NUM);
+        let start = self.internal_cell_offset(cell_index);
+        self.write_bytes(
+            start + INTERNAL_NODE_CELL_CHILD_OFFSET,
+            &child.to_le_bytes(),
+        );
+        self.write_bytes(start + INTERNAL_NODE_CELL_KEY_OFFSET, &key.to_le_bytes());
+    }
+    // the cell pointing at `child`, n_cells when it is the right child
+    fn find_child(&self, child: usize) -> Option<usize> {
+        let n_cells = self.get_n_cells();
+        if self.right_child() as usize == child {
+            return Some(n_cells);
+        }
+        (0..n_cells).find(|&i| self.internal_child(i) as usize == child)
+    }
+    // `child` was split: it now holds the keys up to `child_max_key` and
+    // `new_child` holds the rest, up to the separator `child` used to have
+    fn insert_split_child(&mut self, child: usize, child_max_key: i64, new_child: usize) {
+        let n_cells = self.get_n_cells();
+        let cell_index = self
+            .find_child(child)
+            .expect("ERROR: split child must belong to its parent.");
+        if cell_index == n_cells {
+            self.put_internal_cell(n_cells, child as i32, child_max_key);
+            self.set_right_child(new_child as i32);
+        } else {
+            let separator = self.internal_key(cell_index);
+            let src = self.internal_cell_offset(cell_index)..self.internal_cell_offset(n_cells);
+            let dest = self.internal_cell_offset(cell_index + 1);
+            self.data.copy_within(src, dest);
+            self.put_internal_cell(cell_index, child as i32, child_max_key);
+            self.put_internal_cell(cell_index + 1, new_child as i32, separator);
+        }
+        self.set_n_cells(n_cells + 1);
+    }
+    fn insert_leaf_cell(&mut self, cell_index: usize, cell: LeafCell) {
+        let n_cells = self.get_n_cells();
+        self.push_leaf_cell(&cell.encode());
+        // only the 2-byte slots after the insertion point move
+        let slot = |i: usize| LEAF_NODE_SLOTS_OFFSET + i * LEAF_NODE_SLOT_SIZE;
+        self.data
+            .copy_within(slot(cell_index)..slot(n_cells), slot(cell_index + 1));
+        self.set_leaf_slot(cell_index, n_cells);
+    }
+    fn get_max_key(&self) -> i64 {
+        let index = self.get_n_cells() - 1;
+        match self.kind() {
+            NodeKind::Leaf => self.leaf_key(index),
+            NodeKind::Internal => self.internal_key(index),
+        }
+    }
+    fn get_child_page_index(&self, cell_index: usize) -> usize {
+        match self.kind() {
+            NodeKind::Leaf => {
+                panic!("ERROR: get_child_page_index must be called by internal node.")
+            }
+            NodeKind::Internal => {
+                let n_cells = self.get_n_cells();
+                if cell_index > n_cells {
+                    panic!("cell_index out of bound");
+                } else if cell_index == n_cells {
+                    self.right_child() as usize
+                } else {
+                    self.internal_child(cell_index) as usize
+                }
+            }
+        }
+    }
+}
+
+impl Progress {
+    fn start(n_rows: usize, n_bytes: u64) -> Option<Self> {
+        let now = Instant::now();
+        io::stderr().is_terminal().then_some(Progress {
+            n_rows,
+            n_bytes,
+            start: now,
+            last_draw: now,
+        })
+    }
+
+    fn update(&mut self, n_done: usize) {
+        if self.last_draw.elapsed() >= PROGRESS_REDRAW_INTERVAL {
+            self.last_draw = Instant::now();
+            self.draw(n_done);
+        }
+    }
+
+    // the bar, rows done, rate, time left and how big the file was
+    fn draw(&self, n_done: usize) {
+        let filled = (n_done * PROGRESS_BAR_WIDTH)
+            .checked_div(self.n_rows)
+            .unwrap_or(0);
+        let rate = n_done as f64 / self.start.elapsed().as_secs_f64().max(f64::EPSILON);
+        let eta = (self.n_rows - n_done) as f64 / rate.max(1.0);
+        eprint!(
+            "\r[{}{}] {n_done}/{} rows, {rate:.0} rows/s, eta {eta:.0}s, {} read",
+            "#".repeat(filled),
+            " ".repeat(PROGRESS_BAR_WIDTH - filled),
+            self.n_rows,
+            format_bytes(self.n_bytes)
+        );
+    }
+
+    // the full bar when the load went through, then the line is left as is
+    fn finish(self, n_done: Option<usize>) {
+        if let Some(n_done) = n_done {
+            self.draw(n_done);
+        }
+        eprintln!();
+    }
+}
+
+fn format_bytes(n_bytes: u64) -> String {
+    match n_bytes {
+        0..1024 => format!("{n_bytes} B"),
+        1024..0x10_0000 => format!("{:.1} KB", n_bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", n_bytes as f64 / 0x10_0000 as f64),
+    }
+}
+
+// xorshift64*, good enough for generating workloads and sampling
+struct Rng(u64);
+
+impl Rng {
+    fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng(nanos | 1)
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+// rqlite bench <database> [--rows N] [--mode seq|random|scan]
+fn run_bench(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Some((path, mut flags)) = args.split_first() else {
+        return Err(UsageError.into());
+    };
+    let mut n_rows = BENCH_DEFAULT_ROWS;
+    let mut mode = "seq";
+    while let Some((flag, rest)) = flags.split_first() {
+        let Some((value, rest)) = rest.split_first() else {
+            return Err(UsageError.into());
+        };
+        match flag.as_str() {
+            "--rows" => n_rows = value.parse().map_err(|_| UsageError)?,
+            "--mode" => mode = value.as_str(),
+            _ => return Err(UsageError.into()),
+        }
+        flags = rest;
+    }
+    let mut ids = (1..=n_rows as i64).collect::<Vec<_>>();
+    match mode {
+        "seq" | "scan" => {}
+        "random" => {
+            let mut rng = Rng::from_time();
+            for i in (1..ids.len()).rev() {
+                ids.swap(i, rng.below(i + 1));
+            }
+        }
+        _ => return Err(UsageError.into()),
+    }
+    let pager = Pager::new(path)?;
+    if pager.n_pages != 0 {
+        return Err(ERR_BENCH_NOT_EMPTY.into());
+    }
+    let mut table = Table::new(pager);
+    let mut latencies = Vec::new();
+    let mut n_ops = 0;
+    let started = Instant::now();
+    for id in ids {
+        let row = Row::parse(&[
+            &id.to_string(),
+            &format!("name{id}"),
+            &format!("description{id}"),
+        ])?;
+        let op_started = Instant::now();
+        table.insert_row(row).map_err(|error| {
+            let message = error.to_string();
+            let message = message.trim_start_matches("ERROR: ");
+            format!(
+                "ERROR: bench stopped after {} rows: {message}",
+                latencies.len()
+            )
+        })?;
+        if mode != "scan" {
+            latencies.push(op_started.elapsed());
+            n_ops += 1;
+        }
+    }
+    if mode == "scan" {
+        for _ in 0..BENCH_SCAN_ROUNDS {
+            let op_started = Instant::now();
+            n_ops += table.scan(|_| {})?;
+            latencies.push(op_started.elapsed());
+        }
+    }
+    let elapsed = match mode {
+        "scan" => latencies.iter().sum(),
+        _ => started.elapsed(),
+    };
+    table.flush()?;
+    let file_size = table.pager.file.metadata()?.len();
+    latencies.sort();
+    let percentile = |p: usize| {
+        latencies
+            .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    println!("mode: {mode}");
+    println!("rows: {n_rows}");
+    println!("elapsed: {elapsed:?}");
+    println!(
+        "throughput: {:.0} rows/sec",
+        n_ops as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    );
+    println!(
+        "latency: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        latencies.last().copied().unwrap_or_default()
+    );
+    println!("pages written: {}", table.pager.stats.pages_written);
+    println!("write calls: {}", table.pager.stats.write_calls);
+    println!("file size: {file_size} bytes");
+    table.close()
+}
+
+// the limits are in bytes, that is what the fixed-size fields hold, but the
+// message also gives the character count when the two differ
+fn check_text_len(column: &str, value: &str, max: usize) -> Result<(), Box<dyn Error>> {
+    if value.len() <= max {
+        return Ok(());
+    }
+    let n_chars = value.chars().count();
+    let chars = if n_chars != value.len() {
+        format!(" ({n_chars} characters)")
+    } else {
+        String::new()
+    };
+    Err(format!(
+        "ERROR: {column} is {} bytes{chars}, max {max}: '{value}'.",
+        value.len()
+    )
+    .into())
+}
+
+// drops the NUL padding, and a damaged page shows replacement characters
+// instead of panicking
+fn decode_text(bytes: &[u8]) -> Cow<'_, str> {
+    let len = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&bytes[..len])
+}
+
+// rqlite recover <damaged database> <new database>, for files the pager
+// refuses to open
+fn run_recover(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [path, new_path] = args else {
+        return Err(UsageError.into());
+    };
+    let n_rows = recover(&File::open(path)?, new_path)?;
+    println!("recovered {n_rows} rows into {new_path}.");
+    Ok(())
+}
+
+// an existing database opened read-only, never created the way the repl
+// does
+fn open_table(path: &str) -> Result<Table, Box<dyn Error>> {
+    let file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Err(format!("ERROR: {path} is not an rqlite database.").into());
+    }
+    Pager::from_file(file).map(Table::new)
+}
+
+// rqlite diff [--structure] <database a> <database b>, rows only in a are
+// printed with -, rows only in b with +, and a changed row as both
+fn run_diff(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (structure, paths) = match args {
+        [flag, paths @ ..] if flag == "--structure" => (true, paths),
+        _ => (false, args),
+    };
+    let [path_a, path_b] = paths else {
+        return Err(UsageError.into());
+    };
+    let mut a = open_table(path_a)?;
+    let mut b = open_table(path_b)?;
+    if structure {
+        println!("pages: {} vs {}", a.pager.n_pages, b.pager.n_pages);
+        println!("depth: {} vs {}", a.depth()?, b.depth()?);
+    }
+    let mut rows_a = Vec::new();
+    a.scan(|cell| rows_a.push(cell.value.clone()))?;
+    let mut rows_b = Vec::new();
+    b.scan(|cell| rows_b.push(cell.value.clone()))?;
+    let (mut deleted, mut inserted, mut changed) = (0, 0, 0);
+    let (mut i, mut j) = (0, 0);
+    // both scans come back in key order, so walk them like a merge
+    while i < rows_a.len() || j < rows_b.len() {
+        match (rows_a.get(i), rows_b.get(j)) {
+            (Some(row_a), Some(row_b)) if row_a.id == row_b.id => {
+                if row_a != row_b {
+                    println!("- {row_a}\n+ {row_b}");
+                    changed += 1;
+                }
+                i += 1;
+                j += 1;
+            }
+            (Some(row_a), row_b) if row_b.is_none_or(|row_b| row_a.id < row_b.id) => {
+                println!("- {row_a}");
+                deleted += 1;
+                i += 1;
+            }
+            (_, row_b) => {
+                println!("+ {}", row_b.unwrap());
+                inserted += 1;
+                j += 1;
+            }
+        }
+    }
+    println!("{deleted} deleted, {inserted} inserted, {changed} changed.");
+    a.close()?;
+    b.close()
+}
+
+// fnv-1a, cheap and good enough to catch a damaged stream or page
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// rqlite stats [--json] <database>, walking the tree of a read-only file
+fn run_stats(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (json, path) = match args {
+        [flag, path] if flag == "--json" => (true, path),
+        [path] => (false, path),
+        _ => return Err(UsageError.into()),
+    };
+    let mut table = open_table(path)?;
+    let n_pages = table.pager.n_pages;
+    let (mut n_internal, mut n_leaves, mut n_rows) = (0, 0, 0);
+    // unused space in pages, and unused space in the fixed-width text fields
+    let (mut free_bytes, mut padding_bytes) = (0, 0);
+    let mut fill_histogram = [0; STATS_HISTOGRAM_BUCKETS];
+    let mut visited = Visited::new();
+    let mut stack = vec![table.root_node_index];
+    while let Some(page_index) = stack.pop() {
+        visited.visit(page_index, "child")?;
+        let node = table.pager.read_page(page_index)?;
+        let n_cells = node.get_n_cells();
+        match node.kind() {
+            NodeKind::Internal => {
+                n_internal += 1;
+                free_bytes +=
+                    PAGE_SIZE - INTERNAL_NODE_HEADER_SIZE - n_cells * INTERNAL_NODE_CELL_SIZE;
+                stack.push(node.right_child() as usize);
+                stack.extend((0..n_cells).map(|i| node.internal_child(i) as usize));
+            }
+            NodeKind::Leaf => {
+                n_leaves += 1;
+                n_rows += n_cells;
+                free_bytes += PAGE_SIZE
+                    - LEAF_NODE_HEADER_SIZE
+                    - n_cells * (LEAF_NODE_SLOT_SIZE + LEAF_NODE_CELL_SIZE);
+                for i in 0..n_cells {
+                    let row = LeafCell::decode(node.leaf_cell_bytes(i)).value;
+                    padding_bytes += NAME_MAX_SIZE - row.name().len();
+                    padding_bytes += DESCRIPTION_MAX_SIZE - row.description().len();
+                }
+                let bucket = n_cells * STATS_HISTOGRAM_BUCKETS / (LEAF_NODE_CELL_MAX_NUM + 1);
+                fill_histogram[bucket] += 1;
+            }
+        }
+    }
+    let depth = table.depth()?;
+    // there is no freelist, so pages the tree doesn't reach are lost
+    let n_unreachable = n_pages - 1 - n_internal - n_leaves;
+    table.close()?;
+    let histogram = fill_histogram.map(|n| n.to_string());
+    if json {
+        println!(
+            "{{\"pages\":{n_pages},\"internal_pages\":{n_internal},\"leaf_pages\":{n_leaves},\"unreachable_pages\":{n_unreachable},\"depth\":{depth},\"rows\":{n_rows},\"free_bytes\":{free_bytes},\"padding_bytes\":{padding_bytes},\"leaf_fill_histogram\":[{}]}}",
+            histogram.join(",")
+        );
+    } else {
+        println!("pages: {n_pages}");
+        println!("internal pages: {n_internal}");
+        println!("leaf pages: {n_leaves}");
+        println!("unreachable pages: {n_unreachable}");
+        println!("depth: {depth}");
+        println!("rows: {n_rows}");
+        println!("free bytes: {free_bytes}");
+        println!("padding bytes: {padding_bytes}");
+        println!("leaf fill histogram (10% buckets): {}", histogram.join(" "));
+    }
+    Ok(())
+}
+
+// rqlite dump <database>, writing the rows to stdout in key order
+fn run_dump(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [path] = args else {
+        return Err(UsageError.into());
+    };
+    let mut table = open_table(path)?;
+    let mut out = DUMP_MAGIC.to_vec();
+    let n_rows = table.scan(|cell| {
+        let row = &cell.value;
+        let name = row.name();
+        let description = row.description();
+        // id, name length, name, description
+        let len = ID_SIZE + 1 + name.len() + description.len();
+        out.extend_from_slice(&(len as u32).to_le_bytes());
+        out.extend_from_slice(&row.id.to_le_bytes());
+        out.push(name.len() as u8);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(description.as_bytes());
+    })?;
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&(n_rows as u64).to_le_bytes());
+    out.extend_from_slice(&fnv1a(FNV_OFFSET, &out).to_le_bytes());
+    table.close()?;
+    io::stdout().lock().write_all(&out)?;
+    Ok(())
+}
+
+// rqlite restore <new database>, reading a dump from stdin; the whole
+// stream is checked before any row is written
+fn run_restore(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [new_path] = args else {
+        return Err(UsageError.into());
+    };
+    let mut stream = Vec::new();
+    io::stdin().lock().read_to_end(&mut stream)?;
+    if !stream.starts_with(DUMP_MAGIC) {
+        return Err("ERROR: stdin is not an rqlite dump.".into());
+    }
+    let take = |offset: &mut usize, len: usize| -> Result<&[u8], Box<dyn Error>> {
+        let bytes = stream
+            .get(*offset..*offset + len)
+            .ok_or(ERR_DUMP_TRUNCATED)?;
+        *offset += len;
+        Ok(bytes)
+    };
+    let mut offset = DUMP_MAGIC.len();
+    let mut rows = Vec::new();
+    loop {
+        let len = u32::from_le_bytes(take(&mut offset, 4)?.try_into().unwrap()) as usize;
+        if len == 0 {
+            break;
+        }
+        let record = take(&mut offset, len)?;
+        let name_len = *record.get(ID_SIZE).ok_or(ERR_DUMP_TRUNCATED)? as usize;
+        let text = record.get(ID_SIZE + 1..).ok_or(ERR_DUMP_TRUNCATED)?;
+        if name_len > text.len() {
+            return Err(ERR_DUMP_TRUNCATED.into());
+        }
+        let (name, description) = text.split_at(name_len);
+        let id = i64::from_le_bytes(record[..ID_SIZE].try_into().unwrap());
+        let row = Row::new(
+            id,
+            &String::from_utf8_lossy(name),
+            &String::from_utf8_lossy(description),
+        )
+        .map_err(|error| import_error(format!("row {}", rows.len() + 1), error))?;
+        rows.push(row);
+    }
+    let n_rows = u64::from_le_bytes(take(&mut offset, 8)?.try_into().unwrap());
+    let checksum = fnv1a(FNV_OFFSET, &stream[..offset]);
+    let expected = u64::from_le_bytes(take(&mut offset, 8)?.try_into().unwrap());
+    if checksum != expected || n_rows != rows.len() as u64 || offset != stream.len() {
+        return Err("ERROR: dump checksum mismatch, the stream is damaged.".into());
+    }
+    let pager = Pager::new(new_path)?;
+    if pager.n_pages != 0 {
+        return Err(ERR_RESTORE_NOT_EMPTY.into());
+    }
+    let mut table = Table::new(pager);
+    let n_rows = table.bulk_load(rows)?;
+    table.close()?;
+    println!("restored {n_rows} rows into {new_path}.");
+    Ok(())
+}
+
+// copies the flushed database file into a new snapshot file
+fn snapshot(file: &File, n_pages: usize, path: &str) -> Result<usize, Box<dyn Error>> {
+    let mut pages = Vec::with_capacity(n_pages * PAGE_SIZE);
+    let mut out = SNAPSHOT_MAGIC.to_vec();
+    out.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    for page_index in 0..n_pages {
+        let node = Node::read_at(file, page_index * PAGE_SIZE)?;
+        if page_index == HEADER_PAGE_INDEX {
+            out.extend_from_slice(&node.format_version().unwrap_or(0).to_le_bytes());
+            out.extend_from_slice(&(n_pages as u32).to_le_bytes());
+        }
+        pages.extend_from_slice(&node.data);
+    }
+    for page in pages.chunks(PAGE_SIZE) {
+        out.extend_from_slice(&fnv1a(FNV_OFFSET, page).to_le_bytes());
+    }
+    let hash = fnv1a(fnv1a(FNV_OFFSET, &out), &pages);
+    out.extend_from_slice(&hash.to_le_bytes());
+    out.extend_from_slice(&pages);
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(&out)?;
+    file.sync_all()?;
+    Ok(n_pages)
+}
+
+// rqlite verify <snapshot>, checking the checksums and every page the way
+// the pager would when reading it
+fn run_verify(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [path] = args else {
+        return Err(UsageError.into());
+    };
+    let data = fs::read(path)?;
+    if !data.starts_with(SNAPSHOT_MAGIC) {
+        return Err(format!("ERROR: {path} is not an rqlite snapshot.").into());
+    }
+    let u32_at = |offset: usize| -> Result<u32, Box<dyn Error>> {
+        let bytes = data.get(offset..offset + 4).ok_or(ERR_SNAPSHOT_TRUNCATED)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let version = u32_at(SNAPSHOT_MAGIC.len())?;
+    if version != SNAPSHOT_VERSION {
+        return Err(format!("ERROR: snapshot version {version} is not {SNAPSHOT_VERSION}.").into());
+    }
+    let format_version = u32_at(SNAPSHOT_MAGIC.len() + 4)?;
+    let n_pages = u32_at(SNAPSHOT_MAGIC.len() + 8)? as usize;
+    let checksums_offset = SNAPSHOT_MAGIC.len() + 12;
+    let pages_offset = checksums_offset + n_pages * 8 + 8;
+    if n_pages == 0 || n_pages > PAGE_MAX_NUM || data.len() != pages_offset + n_pages * PAGE_SIZE {
+        return Err(ERR_SNAPSHOT_TRUNCATED.into());
+    }
+    let (manifest, pages) = data.split_at(pages_offset);
+    let (manifest, hash) = manifest.split_at(manifest.len() - 8);
+    let checksums = manifest[checksums_offset..].chunks(8);
+    for (page_index, (page, checksum)) in pages.chunks(PAGE_SIZE).zip(checksums).enumerate() {
+        if fnv1a(FNV_OFFSET, page) != u64::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(format!("ERROR: snapshot page {page_index} checksum mismatch.").into());
+        }
+        let mut node = Node::new();
+        node.data.copy_from_slice(page);
+        node.validate(page_index, n_pages)?;
+    }
+    // what's left for the hash to catch is a damaged manifest
+    if fnv1a(fnv1a(FNV_OFFSET, manifest), pages) != u64::from_le_bytes(hash.try_into().unwrap()) {
+        return Err("ERROR: snapshot hash mismatch.".into());
+    }
+    println!("{path}: ok, {n_pages} pages, format version {format_version}.");
+    Ok(())
+}
+
+// ignores the tree and reads every page that looks like a leaf, keeping the
+// cells that look like rows, then loads them into a new database
+fn recover(file: &File, new_path: &str) -> Result<usize, Box<dyn Error>> {
+    let n_pages = file.metadata()?.len() as usize / PAGE_SIZE;
+    let mut rows = BTreeMap::new();
+    for page_index in 0..n_pages {
+        let node = Node::read_at(file, page_index * PAGE_SIZE)?;
+        if node.data[NODE_KIND_OFFSET] != NodeKind::Leaf.to_u8() {
+            continue;
+        }
+        for i in 0..node.get_n_cells().min(LEAF_NODE_CELL_MAX_NUM) {
+            if node.leaf_slot(i) >= LEAF_NODE_CELL_MAX_NUM {
+                continue;
+            }
+            let cell = LeafCell::decode(node.leaf_cell_bytes(i));
+            let plausible = cell.key > 0
+                && cell.key == cell.value.id
+                && is_plausible_text(&cell.value.name)
+                && is_plausible_text(&cell.value.description);
+            if plausible {
+                rows.entry(cell.key).or_insert(cell.value);
+            }
+        }
+    }
+    let pager = Pager::new(new_path)?;
+    if pager.n_pages != 0 {
+        return Err(ERR_RECOVER_NOT_EMPTY.into());
+    }
+    let mut table = Table::new(pager);
+    let n_rows = table.insert_many(rows.into_values())?;
+    table.close()?;
+    Ok(n_rows)
+}
+
+// utf-8 followed by nothing but NUL padding
+fn is_plausible_text(bytes: &[u8]) -> bool {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    bytes[len..].iter().all(|&b| b == 0) && str::from_utf8(&bytes[..len]).is_ok()
+}
+
+enum SqliteValue {
+    Null,
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+// the whole sqlite3 file, read for .import sqlite
+struct SqliteFile {
+    data: Vec<u8>,
+    page_size: usize,
+    // page size minus the bytes reserved at the end of every page
+    usable_size: usize,
+}
+
+enum SqliteNode {
+    Leaf(Vec<Vec<u8>>),
+    // (child, largest rowid under it) for every child but the last
+    Interior {
+        cells: Vec<(usize, i64)>,
+        right_child: usize,
+    },
+}
+
+// sqlite varints are big-endian, 7 bits per byte, and the 9th byte carries
+// a full 8 bits
+fn put_sqlite_varint(buf: &mut Vec<u8>, value: u64) {
+    if value >> 56 != 0 {
+        for i in (1..9).rev() {
+            buf.push((value >> (i * 7 + 1)) as u8 | 0x80);
+        }
+        buf.push(value as u8);
+        return;
+    }
+    let n_bytes = (1..9).find(|&n| value >> (n * 7) == 0).unwrap();
+    for i in (1..n_bytes).rev() {
+        buf.push((value >> (i * 7)) as u8 | 0x80);
+    }
+    buf.push(value as u8 & 0x7f);
+}
+
+fn sqlite_record(values: &[SqliteValue]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        match value {
+            SqliteValue::Null => put_sqlite_varint(&mut types, 0),
+            SqliteValue::Int(int) => {
+                // serial types 1 to 6 hold 1, 2, 3, 4, 6 and 8 byte integers
+                let (serial_type, size) = [(1, 1), (2, 2), (3, 3), (4, 4), (5, 6), (6, 8)]
+                    .into_iter()
+                    .find(|&(_, size)| size == 8 || matches!(int >> (size * 8 - 1), 0 | -1))
+                    .unwrap();
+                put_sqlite_varint(&mut types, serial_type);
+                body.extend_from_slice(&int.to_be_bytes()[8 - size..]);
+            }
+            SqliteValue::Float(float) => {
+                put_sqlite_varint(&mut types, 7);
+                body.extend_from_slice(&float.to_be_bytes());
+            }
+            SqliteValue::Text(text) => {
+                put_sqlite_varint(&mut types, text.len() as u64 * 2 + 13);
+                body.extend_from_slice(text.as_bytes());
+            }
+            SqliteValue::Blob(blob) => {
+                put_sqlite_varint(&mut types, blob.len() as u64 * 2 + 12);
+                body.extend_from_slice(blob);
+            }
+        }
+    }
+    // the header size counts its own varint
+    let mut header_size = types.len() + 1;
+    if header_size >= 0x80 {
+        header_size += 1;
+    }
+    let mut record = Vec::new();
+    put_sqlite_varint(&mut record, header_size as u64);
+    record.extend(types);
+    record.extend(body);
+    record
+}
+
+fn sqlite_leaf_cell(rowid: i64, record: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::new();
+    put_sqlite_varint(&mut cell, record.len() as u64);
+    put_sqlite_varint(&mut cell, rowid as u64);
+    cell.extend_from_slice(record);
+    cell
+}
+
+// one b-tree page, its header at `offset` (100 on page 1), cells packed at
+// the end of the page in key order
+fn sqlite_page(offset: usize, kind: u8, cells: &[Vec<u8>], right_child: Option<u32>) -> Vec<u8> {
+    let mut page = vec![0u8; SQLITE_PAGE_SIZE];
+    let header_size = if right_child.is_some() { 12 } else { 8 };
+    let mut content_start = SQLITE_PAGE_SIZE;
+    for (i, cell) in cells.iter().enumerate() {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        let pointer = offset + header_size + i * 2;
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+    }
+    page[offset] = kind;
+    page[offset + 3..offset + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    page[offset + 5..offset + 7].copy_from_slice(&(content_start as u16).to_be_bytes());
+    if let Some(right_child) = right_child {
+        page[offset + 8..offset + 12].copy_from_slice(&right_child.to_be_bytes());
+    }
+    page
+}
+
+// builds the table b-tree bottom-up like bulk_load: leaves packed in rowid
+// order, then interior levels until one node is left, which becomes page 2
+fn write_sqlite(rows: &[Row], path: &str) -> Result<(), Box<dyn Error>> {
+    let mut nodes = Vec::new();
+    let mut level = Vec::new();
+    let mut cells = Vec::new();
+    let mut used = 8;
+    let mut max_key = 0;
+    for row in rows {
+        let record = sqlite_record(&[
+            SqliteValue::Null,
+            SqliteValue::Text(row.name().into_owned()),
+            SqliteValue::Text(row.description().into_owned()),
+        ]);
+        let cell = sqlite_leaf_cell(row.id, &record);
+        if used + cell.len() + 2 > SQLITE_PAGE_SIZE {
+            level.push((nodes.len(), max_key));
+            nodes.push(SqliteNode::Leaf(mem::take(&mut cells)));
+            used = 8;
+        }
+        used += cell.len() + 2;
+        cells.push(cell);
+        max_key = row.id;
+    }
+    level.push((nodes.len(), max_key));
+    nodes.push(SqliteNode::Leaf(cells));
+    // 12-byte header, then a pointer, child and rowid varint per cell
+    let fan_out = (SQLITE_PAGE_SIZE - 12) / (2 + 4 + 9);
+    while level.len() > 1 {
+        let n_nodes = level.len().div_ceil(fan_out);
+        let mut next_level = Vec::new();
+        for children in level.chunks(level.len().div_ceil(n_nodes)) {
+            let (&(right_child, max_key), cells) = children.split_last().unwrap();
+            next_level.push((nodes.len(), max_key));
+            nodes.push(SqliteNode::Interior {
+                cells: cells.to_vec(),
+                right_child,
+            });
+        }
+        level = next_level;
+    }
+    // the root goes on page 2 right after the schema, the rest follow it
+    let root = nodes.len() - 1;
+    let page_number = |node: usize| {
+        if node == root {
+            SQLITE_TABLE_ROOT_PAGE
+        } else {
+            SQLITE_TABLE_ROOT_PAGE + 1 + node as u32
+        }
+    };
+    let n_pages = 1 + nodes.len() as u32;
+    let schema_record = sqlite_record(&[
+        SqliteValue::Text("table".to_string()),
+        SqliteValue::Text(SQLITE_TABLE_NAME.to_string()),
+        SqliteValue::Text(SQLITE_TABLE_NAME.to_string()),
+        SqliteValue::Int(SQLITE_TABLE_ROOT_PAGE as i64),
+        SqliteValue::Text(SQLITE_TABLE_SQL.to_string()),
+    ]);
+    let mut first_page = sqlite_page(
+        SQLITE_HEADER_SIZE,
+        SQLITE_TABLE_LEAF,
+        &[sqlite_leaf_cell(1, &schema_record)],
+        None,
+    );
+    let header = &mut first_page[..SQLITE_HEADER_SIZE];
+    header[..16].copy_from_slice(SQLITE_MAGIC);
+    header[16..18].copy_from_slice(&(SQLITE_PAGE_SIZE as u16).to_be_bytes());
+    // file format versions, no reserved bytes, fixed payload fractions
+    header[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+    // change counter, page count, schema cookie, schema format 4, utf-8,
+    // version-valid-for and the sqlite version number
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&n_pages.to_be_bytes());
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+    header[96..100].copy_from_slice(&3_040_000u32.to_be_bytes());
+    let mut pages = vec![Vec::new(); nodes.len()];
+    for (node_index, node) in nodes.iter().enumerate() {
+        let page = match node {
+            SqliteNode::Leaf(cells) => sqlite_page(0, SQLITE_TABLE_LEAF, cells, None),
+            SqliteNode::Interior { cells, right_child } => {
+                let cells = cells
+                    .iter()
+                    .map(|&(child, max_key)| {
+                        let mut cell = page_number(child).to_be_bytes().to_vec();
+                        put_sqlite_varint(&mut cell, max_key as u64);
+                        cell
+                    })
+                    .collect::<Vec<_>>();
+                sqlite_page(
+                    0,
+                    SQLITE_TABLE_INTERIOR,
+                    &cells,
+                    Some(page_number(*right_child)),
+                )
+            }
+        };
+        pages[(page_number(node_index) - SQLITE_TABLE_ROOT_PAGE) as usize] = page;
+    }
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(&first_page)?;
+    for page in pages {
+        file.write_all(&page)?;
+    }
+    Ok(())
+}
+
+fn get_sqlite_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in buf.iter().enumerate().take(9) {
+        if i == 8 {
+            return Some(((value << 8) | byte as u64, 9));
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn parse_sqlite_record(record: &[u8]) -> Option<Vec<SqliteValue>> {
+    let (header_size, mut offset) = get_sqlite_varint(record)?;
+    let mut body = header_size as usize;
+    let mut values = Vec::new();
+    while offset < header_size as usize {
+        let (serial_type, len) = get_sqlite_varint(record.get(offset..)?)?;
+        offset += len;
+        let size = match serial_type {
+            0 | 8 | 9 => 0,
+            1..=4 => serial_type as usize,
+            5 => 6,
+            6 | 7 => 8,
+            10 | 11 => return None,
+            _ => (serial_type as usize - 12) / 2,
+        };
+        let bytes = record.get(body..body + size)?;
+        body += size;
+        values.push(match serial_type {
+            0 => SqliteValue::Null,
+            8 => SqliteValue::Int(0),
+            9 => SqliteValue::Int(1),
+            7 => SqliteValue::Float(f64::from_be_bytes(bytes.try_into().ok()?)),
+            1..=6 => {
+                // sign extend from the top byte
+                let fill = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+                let mut int = [fill; 8];
+                int[8 - size..].copy_from_slice(bytes);
+                SqliteValue::Int(i64::from_be_bytes(int))
+            }
+            _ if serial_type % 2 == 0 => SqliteValue::Blob(bytes.to_vec()),
+            _ => SqliteValue::Text(String::from_utf8_lossy(bytes).into_owned()),
+        });
+    }
+    Some(values)
+}
+
+impl SqliteFile {
+    fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read(path)?;
+        if data.len() < SQLITE_HEADER_SIZE || data[..SQLITE_MAGIC.len()] != *SQLITE_MAGIC {
+            return Err(format!("ERROR: {path} is not a sqlite3 database.").into());
+        }
+        let page_size = match u16::from_be_bytes([data[16], data[17]]) {
+            1 => 65536,
+            page_size => page_size as usize,
+        };
+        Ok(SqliteFile {
+            usable_size: page_size - data[20] as usize,
+            data,
+            page_size,
+        })
+    }
+
+    fn page(&self, page_number: u32) -> Option<&[u8]> {
+        let start = (page_number as usize).checked_sub(1)? * self.page_size;
+        self.data.get(start..start + self.page_size)
+    }
+
+    // the payload of a table leaf cell, following the overflow chain when
+    // it doesn't fit in the page
+    fn payload(&self, page: &[u8], cell: usize) -> Option<(i64, Vec<u8>)> {
+        let (payload_size, len) = get_sqlite_varint(page.get(cell..)?)?;
+        let (rowid, rowid_len) = get_sqlite_varint(page.get(cell + len..)?)?;
+        let start = cell + len + rowid_len;
+        let payload_size = payload_size as usize;
+        let usable_size = self.usable_size;
+        let max_local = usable_size - 35;
+        let local_size = if payload_size <= max_local {
+            payload_size
+        } else {
+            let min_local = (usable_size - 12) * 32 / 255 - 23;
+            let size = min_local + (payload_size - min_local) % (usable_size - 4);
+            if size <= max_local { size } else { min_local }
+        };
+        let mut payload = page.get(start..start + local_size)?.to_vec();
+        let mut overflow = page.get(start + local_size..start + local_size + 4);
+        while payload.len() < payload_size {
+            let overflow_page = self.page(u32::from_be_bytes(overflow?.try_into().ok()?))?;
+            let size = (payload_size - payload.len()).min(usable_size - 4);
+            payload.extend_from_slice(overflow_page.get(4..4 + size)?);
+            overflow = overflow_page.get(..4);
+        }
+        Some((rowid as i64, payload))
+    }
+
+    // every (rowid, record) of the table b-tree rooted at `page_number`
+    fn table_rows(
+        &self,
+        page_number: u32,
+        depth: usize,
+        rows: &mut Vec<(i64, Vec<SqliteValue>)>,
+    ) -> Option<()> {
+        if depth > SQLITE_MAX_DEPTH {
+            return None;
+        }
+        let page = self.page(page_number)?;
+        let header = if page_number == 1 {
+            SQLITE_HEADER_SIZE
+        } else {
+            0
+        };
+        let kind = *page.get(header)?;
+        let n_cells = u16::from_be_bytes(page.get(header + 3..header + 5)?.try_into().ok()?);
+        let header_size = if kind == SQLITE_TABLE_INTERIOR { 12 } else { 8 };
+        for i in 0..n_cells as usize {
+            let pointer = header + header_size + i * 2;
+            let cell = u16::from_be_bytes(page.get(pointer..pointer + 2)?.try_into().ok()?);
+            let cell = cell as usize;
+            match kind {
+                SQLITE_TABLE_LEAF => {
+                    let (rowid, payload) = self.payload(page, cell)?;
+                    rows.push((rowid, parse_sqlite_record(&payload)?));
+                }
+                SQLITE_TABLE_INTERIOR => {
+                    let child = u32::from_be_bytes(page.get(cell..cell + 4)?.try_into().ok()?);
+                    self.table_rows(child, depth + 1, rows)?;
+                }
+                _ => return None,
+            }
+        }
+        if kind == SQLITE_TABLE_INTERIOR {
+            let right_child = page.get(header + 8..header + 12)?.try_into().ok()?;
+            self.table_rows(u32::from_be_bytes(right_child), depth + 1, rows)?;
+        }
+        Some(())
+    }
+}
+
+// the column list of a CREATE TABLE statement as (name, declared type,
+// is the integer primary key), table constraints are skipped
+fn sqlite_columns(sql: &str) -> Vec<(String, String, bool)> {
+    const CONSTRAINTS: [&str; 11] = [
+        "CONSTRAINT",
+        "PRIMARY",
+        "NOT",
+        "NULL",
+        "UNIQUE",
+        "CHECK",
+        "DEFAULT",
+        "COLLATE",
+        "REFERENCES",
+        "GENERATED",
+        "AS",
+    ];
+    let (Some(start), Some(end)) = (sql.find('('), sql.rfind(')')) else {
+        return Vec::new();
+    };
+    let mut defs = Vec::new();
+    let mut depth = 0;
+    let mut def_start = start + 1;
+    for (i, c) in sql[..end].char_indices().skip_while(|&(i, _)| i <= start) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                defs.push(&sql[def_start..i]);
+                def_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    defs.push(&sql[def_start..end]);
+    let mut columns = Vec::new();
+    for def in defs {
+        let upper = def.to_uppercase();
+        let mut tokens = upper.split_whitespace();
+        let Some(first) = tokens.next() else {
+            continue;
+        };
+        if ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"].contains(&first) {
+            continue;
+        }
+        let name = def.split_whitespace().next().unwrap();
+        let name = name.trim_matches(['"', '`', '[', ']']).to_string();
+        let kind = tokens
+            .take_while(|token| !CONSTRAINTS.contains(token))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let is_rowid = kind == "INTEGER" && upper.contains("PRIMARY KEY");
+        columns.push((name, kind, is_rowid));
+    }
+    columns
+}
+
+fn sqlite_text(value: &SqliteValue) -> Cow<'_, str> {
+    match value {
+        SqliteValue::Null => Cow::Borrowed(""),
+        SqliteValue::Int(int) => Cow::Owned(int.to_string()),
+        SqliteValue::Float(float) => Cow::Owned(float.to_string()),
+        SqliteValue::Text(text) => Cow::Borrowed(text),
+        SqliteValue::Blob(blob) => String::from_utf8_lossy(blob),
+    }
+}
+
+// rows of the only table in a sqlite3 file: the id is its integer primary
+// key (or first integer column, or the rowid), name and description its
+// first two text columns
+fn read_sqlite(path: &str) -> Result<Vec<Row>, Box<dyn Error>> {
+    let file = SqliteFile::open(path)?;
+    let mut schema = Vec::new();
+    file.table_rows(1, 0, &mut schema)
+        .ok_or(ERR_SQLITE_CORRUPT)?;
+    let tables = schema
+        .iter()
+        .filter_map(|(_, values)| match values.as_slice() {
+            [
+                SqliteValue::Text(kind),
+                SqliteValue::Text(name),
+                _,
+                SqliteValue::Int(root_page),
+                SqliteValue::Text(sql),
+            ] if kind == "table" && !name.starts_with("sqlite_") => Some((*root_page, sql)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let [(root_page, sql)] = tables[..] else {
+        return Err(format!(
+            "ERROR: sqlite import needs exactly one table, found {}.",
+            tables.len()
+        )
+        .into());
+    };
+    let columns = sqlite_columns(sql);
+    let id_column = columns
+        .iter()
+        .position(|(_, _, is_rowid)| *is_rowid)
+        .or_else(|| columns.iter().position(|(_, kind, _)| kind.contains("INT")));
+    let is_rowid = id_column.is_none_or(|i| columns[i].2);
+    let text_columns = columns
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, kind, _))| ["CHAR", "CLOB", "TEXT"].iter().any(|t| kind.contains(t)))
+        .map(|(i, _)| i)
+        .take(2)
+        .collect::<Vec<_>>();
+    let Some(&name_column) = text_columns.first() else {
+        return Err("ERROR: sqlite import needs a TEXT column for name.".into());
+    };
+    let description_column = text_columns.get(1).copied();
+    let mut records = Vec::new();
+    file.table_rows(root_page as u32, 0, &mut records)
+        .ok_or(ERR_SQLITE_CORRUPT)?;
+    let mut rows = Vec::new();
+    for (rowid, values) in records {
+        let value = |column: usize| values.get(column).unwrap_or(&SqliteValue::Null);
+        let id = match id_column.map(value) {
+            _ if is_rowid => rowid,
+            Some(SqliteValue::Int(id)) => *id,
+            _ => return Err(format!("ERROR: rowid {rowid}: id is not an integer.").into()),
+        };
+        let description = description_column.map_or(Cow::Borrowed(""), |i| sqlite_text(value(i)));
+        let row = Row::new(id, &sqlite_text(value(name_column)), &description)
+            .map_err(|error| import_error(format!("rowid {rowid}"), error))?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn put_json_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// one {"id":..,"name":..,"description":..} object per line
+fn write_jsonl(rows: &[Row], path: &str) -> Result<(), Box<dyn Error>> {
+    let mut out = String::new();
+    for row in rows {
+        out.push_str(&format!("{{\"id\":{},\"name\":", row.id));
+        put_json_string(&mut out, &row.name());
+        out.push_str(",\"description\":");
+        put_json_string(&mut out, &row.description());
+        out.push_str("}\n");
+    }
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(out.as_bytes())?;
+    Ok(())
+}
+
+// just enough json for flat objects of strings, numbers, booleans and
+// nulls; values come back as text, with None for null
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{expected}', found '{c}'")),
+            None => Err(format!("expected '{expected}', found end of line")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let hex = (0..4).filter_map(|_| self.chars.next()).collect::<String>();
+        u32::from_str_radix(&hex, 16).map_err(|_| format!("bad escape '\\u{hex}'"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            match self.chars.next().ok_or("unterminated string")? {
+                '"' => return Ok(text),
+                '\\' => {
+                    let c = match self.chars.next().ok_or("unterminated string")? {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // a utf-16 surrogate pair spells one code point
+                            if (0xd800..0xdc00).contains(&code) {
+                                self.expect('\\')?;
+                                self.expect('u')?;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(format!("bad surrogate pair '\\u{low:x}'"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or(format!("bad escape '\\u{code:x}'"))?
+                        }
+                        c => c,
+                    };
+                    text.push(c);
+                }
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Option<String>, String> {
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'"') {
+            return self.string().map(Some);
+        }
+        let mut token = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_alphanumeric() || "+-.".contains(*c))
+        {
+            token.push(c);
+        }
+        match token.as_str() {
+            "" => Err("expected a string, number, boolean or null".to_string()),
+            "null" => Ok(None),
+            _ => Ok(Some(token)),
+        }
+    }
+
+    fn object(line: &str) -> Result<BTreeMap<String, Option<String>>, String> {
+        let mut parser = JsonParser {
+            chars: line.chars().peekable(),
+        };
+        let mut object = BTreeMap::new();
+        parser.expect('{')?;
+        parser.skip_whitespace();
+        if parser.chars.next_if_eq(&'}').is_none() {
+            loop {
+                let key = parser.string()?;
+                parser.expect(':')?;
+                object.insert(key, parser.value()?);
+                parser.skip_whitespace();
+                match parser.chars.next() {
+                    Some(',') => continue,
+                    Some('}') => break,
+                    _ => return Err("expected ',' or '}'".to_string()),
+                }
+            }
+        }
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(object),
+            Some(c) => Err(format!("unexpected '{c}' after object")),
+        }
+    }
+}
+
+// one json object per line keyed by column name, other keys are ignored
+fn read_jsonl(path: &str) -> Result<Vec<Row>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)?;
+    let mut rows = Vec::new();
+    for (line_no, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let position = format!("line {}", line_no + 1);
+        let object = JsonParser::object(line)
+            .map_err(|message| import_error(position.clone(), format!("{message}.").into()))?;
+        let field = |key: &str| object.get(key).cloned().flatten();
+        let Some(id) = field("id") else {
+            return Err(import_error(position, "missing id.".into()));
+        };
+        let id = id
+            .parse::<i64>()
+            .map_err(|_| format!("ERROR: {position}: id '{id}' is not an integer."))?;
+        let name = field("name").unwrap_or_default();
+        let description = field("description").unwrap_or_default();
+        let row =
+            Row::new(id, &name, &description).map_err(|error| import_error(position, error))?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn parse_delimiter(value: &str) -> Result<char, Box<dyn Error>> {
+    let mut chars = value.chars();
+    match (value, chars.next(), chars.next()) {
+        ("tab" | "\\t", _, _) => Ok('\t'),
+        (_, Some(c), None) => Ok(c),
+        _ => Err(format!("ERROR: delimiter '{value}' is not a single character.").into()),
+    }
+}
+
+// one row per line, by default `id,name,description`; with a header line
+// or --columns the fields are picked out by name
+fn read_csv(path: &str, options: &CsvOptions) -> Result<(Vec<Row>, usize), Box<dyn Error>> {
+    let content = fs::read_to_string(path)?;
+    let delimiter = options.delimiter.unwrap_or(',');
+    let split = |line| {
+        str::split(line, delimiter)
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let header = if options.header {
+        lines.next().map(|(_, line)| split(line))
+    } else {
+        None
+    };
+    let columns = match (&options.columns, header) {
+        (Some(columns), _) => {
+            if let Some(column) = columns
+                .iter()
+                .find(|column| !["id", "name", "description", "-"].contains(&column.as_str()))
+            {
+                return Err(format!(
+                    "ERROR: unknown column '{column}', expected id, name, description or -."
+                )
+                .into());
+            }
+            columns.clone()
+        }
+        (None, Some(header)) => header.iter().map(|name| name.to_lowercase()).collect(),
+        (None, None) => vec![
+            "id".to_string(),
+            "name".to_string(),
+            "description".to_string(),
+        ],
+    };
+    let position = |field: &str| columns.iter().position(|column| column == field);
+    let id_column = position("id").ok_or("ERROR: no id column in the csv.")?;
+    let name_column = position("name");
+    let description_column = position("description");
+    let mut log = match &options.on_error {
+        OnError::Log(path) => Some(File::create(path)?),
+        _ => None,
+    };
+    let mut rows = Vec::new();
+    let mut n_skipped = 0;
+    for (line_no, line) in lines {
+        let fields = split(line);
+        let field = |column: Option<usize>| {
+            let value = column.map_or("", |i| fields[i]);
+            if options.null_marker.as_deref() == Some(value) {
+                None
+            } else {
+                Some(value)
+            }
+        };
+        let row = if fields.len() != columns.len() {
+            Err(format!(
+                "ERROR: expected {} fields, got {}.",
+                columns.len(),
+                fields.len()
+            )
+            .into())
+        } else {
+            match field(Some(id_column)) {
+                None => Err("ERROR: id is null.".into()),
+                Some(id) => match id.parse::<i64>() {
+                    Ok(id) => Row::new(
+                        id,
+                        field(name_column).unwrap_or_default(),
+                        field(description_column).unwrap_or_default(),
+                    ),
+                    Err(_) => Err(format!("ERROR: id '{id}' is not an integer.").into()),
+                },
+            }
+        };
+        match row {
+            Ok(row) => rows.push(row),
+            Err(error) => {
+                let error = import_error(format!("line {}", line_no + 1), error);
+                match (&options.on_error, &mut log) {
+                    (OnError::Abort, _) => return Err(error),
+                    (_, Some(log)) => writeln!(log, "{error}")?,
+                    _ => {}
+                }
+                n_skipped += 1;
+            }
+        }
+    }
+    Ok((rows, n_skipped))
+}
+
+// select [count(*) | sample N | first | last | after <id> [limit N]
+//     | in (<id>, ...) | resume <bookmark> [limit N]]
+//     [* | expr, ...] [where expr [and expr ...]]
+//     [order by expr [asc|desc]]
+struct Query {
+    // select count(*), counting the rows that pass the filter
+    count: bool,
+    access: Access,
+    // None for *
+    projection: Option<Vec<Expr>>,
+    filter: Vec<Expr>,
+    // the sort key, and whether it sorts descending
+    order_by: Option<(Expr, bool)>,
+}
+
+// how a select reaches its rows
+#[derive(Clone)]
+enum Access {
+    Scan,
+    // this many distinct rows picked by random descents, in key order
+    Sample(usize),
+    // the row with the smallest or the largest id
+    First,
+    Last,
+    // the rows with ids above this one, at most limit of them
+    After(i64, Option<usize>),
+    // the rows with these ids, in key order
+    In(Vec<i64>),
+    // the rows from a bookmark on, at most limit of them
+    Resume(Bookmark, Option<usize>),
+}
+
+// sorts rows by a key in memory, spilling each SORT_RUN_ROWS sorted rows
+// to a temp file and merging the runs at the end
+struct Sorter {
+    descending: bool,
+    buffer: Vec<(Value, Vec<Value>)>,
+    runs: Vec<File>,
+}
+
+enum Expr {
+    Column(Column),
+    Int(i64),
+    Text(String),
+    Call(Function, Vec<Expr>),
+    Compare(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy)]
+enum Column {
+    Id,
+    Name,
+    Description,
+}
+
+#[derive(Clone, Copy)]
+enum Function {
+    Length,
+    Upper,
+    Lower,
+    Substr,
+    Abs,
+    LastInsertRowid,
+    Changes,
+    TotalChanges,
+    JsonExtract,
+}
+
+// Null sorts before everything, as in sqlite
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Value {
+    Null,
+    Int(i64),
+    Text(String),
+}
+
+#[derive(PartialEq)]
+enum QueryToken {
+    Word(String),
+    Int(i64),
+    Text(String),
+    Symbol(&'static str),
+}
+
+// the tokens and the byte offset each starts at
+fn lex_query(text: &str) -> Result<(Vec<QueryToken>, Vec<usize>), Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut offsets = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let offset = text.len() - rest.len();
+        offsets.push(offset);
+        let len = if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(QueryToken::Word(rest[..len].to_lowercase()));
+            len
+        } else if c.is_ascii_digit()
+            || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let len = 1 + rest[1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len() - 1);
+            let int = rest[..len].parse().map_err(|_| {
+                let message = format!("ERROR: integer '{}' out of range.", &rest[..len]);
+                SyntaxError::at(offset, message)
+            })?;
+            tokens.push(QueryToken::Int(int));
+            len
+        } else if c == '\'' {
+            // '' inside a string stands for one quote
+            let mut text = String::new();
+            let mut chars = rest.char_indices().skip(1).peekable();
+            let len = loop {
+                match chars.next() {
+                    Some((i, '\'')) => {
+                        if chars.next_if(|&(_, c)| c == '\'').is_none() {
+                            break i + 1;
+                        }
+                        text.push('\'');
+                    }
+                    Some((_, c)) => text.push(c),
+                    None => {
+                        let message = "ERROR: unterminated string.".to_string();
+                        return Err(SyntaxError::at(offset, message).into());
+                    }
+                }
+            };
+            tokens.push(QueryToken::Text(text));
+            len
+        } else {
+            let Some(symbol) = QUERY_SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+            else {
+                let message = format!("ERROR: unexpected '{c}'.");
+                return Err(SyntaxError::at(offset, message).into());
+            };
+            tokens.push(QueryToken::Symbol(symbol));
+            symbol.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok((tokens, offsets))
+}
+
+impl Query {
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let (mut tokens, offsets) = lex_query(text)?;
+        let count_all = [
+            QueryToken::Word("count".to_string()),
+            QueryToken::Symbol("("),
+            QueryToken::Symbol("*"),
+            QueryToken::Symbol(")"),
+        ];
+        let count = tokens.starts_with(&count_all);
+        if count {
+            tokens.drain(..count_all.len());
+        }
+        let access = match tokens.first() {
+            Some(QueryToken::Word(word)) if !count => match word.as_str() {
+                "sample" => match tokens.get(1) {
+                    Some(&QueryToken::Int(n)) if n >= 0 => Access::Sample(n as usize),
+                    _ => return Err("ERROR: sample needs a number of rows.".into()),
+                },
+                "first" => Access::First,
+                "last" => Access::Last,
+                "after" => {
+                    let Some(&QueryToken::Int(id)) = tokens.get(1) else {
+                        return Err("ERROR: after needs an id.".into());
+                    };
+                    Access::After(id, parse_limit(&tokens[2..])?)
+                }
+                "resume" => {
+                    let bookmark = match tokens.get(1) {
+                        Some(QueryToken::Word(word)) => word.parse::<Bookmark>()?,
+                        _ => return Err(ERR_BOOKMARK_SYNTAX.into()),
+                    };
+                    Access::Resume(bookmark, parse_limit(&tokens[2..])?)
+                }
+                "in" => {
+                    let mut ids = Vec::new();
+                    let mut rest = tokens[1..].iter();
+                    if rest.next() != Some(&QueryToken::Symbol("(")) {
+                        return Err(ERR_IN_SYNTAX.into());
+                    }
+                    loop {
+                        let Some(&QueryToken::Int(id)) = rest.next() else {
+                            return Err(ERR_IN_SYNTAX.into());
+                        };
+                        ids.push(id);
+                        match rest.next() {
+                            Some(QueryToken::Symbol(",")) => continue,
+                            Some(QueryToken::Symbol(")")) => break,
+                            _ => return Err(ERR_IN_SYNTAX.into()),
+                        }
+                    }
+                    Access::In(ids)
+                }
+                _ => Access::Scan,
+            },
+            _ => Access::Scan,
+        };
+        tokens.drain(..access.n_tokens());
+        let mut tokens = tokens.into_iter().peekable();
+        // an unexpected token was the last one taken
+        Self::parse_clauses(count, access, &mut tokens).map_err(|error| {
+            match error.downcast::<SyntaxError>() {
+                Ok(mut error) => {
+                    let offset = offsets[offsets.len() - tokens.len() - 1];
+                    error.offset.get_or_insert(offset);
+                    error
+                }
+                Err(error) => error,
+            }
+        })
+    }
+
+    // what follows the access keywords: the projection, where and order by
+    fn parse_clauses(
+        count: bool,
+        access: Access,
+        tokens: &mut QueryTokens,
+    ) -> Result<Self, Box<dyn Error>> {
+        let projection = match tokens.peek() {
+            _ if count => None,
+            None => None,
+            Some(QueryToken::Word(word)) if word == "where" || word == "order" => None,
+            Some(QueryToken::Symbol("*")) => {
+                tokens.next();
+                None
+            }
+            Some(_) => {
+                let mut exprs = vec![Expr::parse(tokens)?];
+                while tokens.next_if_eq(&QueryToken::Symbol(",")).is_some() {
+                    exprs.push(Expr::parse(tokens)?);
+                }
+                Some(exprs)
+            }
+        };
+        let mut filter = Vec::new();
+        if next_word(tokens, "where") {
+            filter.push(Expr::parse_comparison(tokens)?);
+            while next_word(tokens, "and") {
+                filter.push(Expr::parse_comparison(tokens)?);
+            }
+        }
+        let mut order_by = None;
+        if next_word(tokens, "order") {
+            if !next_word(tokens, "by") {
+                return Err("ERROR: order needs by.".into());
+            }
+            let key = Expr::parse(tokens)?;
+            let descending = next_word(tokens, "desc");
+            if !descending {
+                next_word(tokens, "asc");
+            }
+            order_by = Some((key, descending));
+        }
+        if count && order_by.is_some() {
+            return Err("ERROR: count(*) gives one row, there is nothing to order.".into());
+        }
+        if !matches!(access, Access::Scan) && (!filter.is_empty() || order_by.is_some()) {
+            return Err(format!("ERROR: {} takes no where or order by.", access.keyword()).into());
+        }
+        if let Some(token) = tokens.next() {
+            return Err(token.unexpected().into());
+        }
+        Ok(Query {
+            count,
+            access,
+            projection,
+            filter,
+            order_by,
+        })
+    }
+
+    // a query that reads no column, like select last_insert_rowid(), gives
+    // one row without scanning the table
+    fn uses_columns(&self) -> bool {
+        self.projection
+            .as_ref()
+            .is_none_or(|exprs| exprs.iter().any(Expr::uses_columns))
+            || self.filter.iter().any(Expr::uses_columns)
+    }
+
+    // the projected values of a row, or None when the where clause drops it
+    fn eval(
+        &self,
+        row: Option<&Row>,
+        changes: &Changes,
+    ) -> Result<Option<Vec<Value>>, Box<dyn Error>> {
+        for predicate in &self.filter {
+            if !predicate.eval(row, changes)?.is_true() {
+                return Ok(None);
+            }
+        }
+        let values = match (&self.projection, row) {
+            (None, Some(row)) => [Column::Id, Column::Name, Column::Description]
+                .iter()
+                .map(|column| column.value(row))
+                .collect(),
+            (None, None) => Vec::new(),
+            (Some(exprs), _) => exprs
+                .iter()
+                .map(|expr| expr.eval(row, changes))
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(Some(values))
+    }
+}
+
+// [limit N] after the access keyword
+fn parse_limit(tokens: &[QueryToken]) -> Result<Option<usize>, Box<dyn Error>> {
+    match tokens {
+        [QueryToken::Word(word), QueryToken::Int(n), ..] if word == "limit" && *n >= 0 => {
+            Ok(Some(*n as usize))
+        }
+        [QueryToken::Word(word), ..] if word == "limit" => {
+            Err("ERROR: limit needs a number of rows.".into())
+        }
+        _ => Ok(None),
+    }
+}
+
+// a leaf walk of at most limit rows, starting pages_before pages in
+fn leaf_scan_plan(limit: Option<usize>, pages_before: usize) -> Vec<String> {
+    match limit {
+        Some(limit) => vec![
+            format!("scan along the leaves for at most {limit} rows"),
+            format!(
+                "pages expected: about {}",
+                pages_before + limit.div_ceil(LEAF_NODE_CELL_MAX_NUM)
+            ),
+        ],
+        None => vec!["scan along the leaves to the end".to_string()],
+    }
+}
+
+// written as bm<page><cell><key> in hex, a word the select lexer reads whole
+impl fmt::Display for Bookmark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "bm{:08x}{:04x}{:016x}",
+            self.page_index, self.cell_index, self.key as u64
+        )
+    }
+}
+
+impl FromStr for Bookmark {
+    type Err = Box<dyn Error>;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let hex = text
+            .strip_prefix("bm")
+            .filter(|hex| hex.len() == 28 && hex.is_ascii())
+            .ok_or(ERR_BOOKMARK_SYNTAX)?;
+        let field = |range: Range<usize>| {
+            u64::from_str_radix(&hex[range], 16).map_err(|_| ERR_BOOKMARK_SYNTAX)
+        };
+        Ok(Bookmark {
+            page_index: field(0..8)? as usize,
+            cell_index: field(8..12)? as usize,
+            key: field(12..28)? as i64,
+        })
+    }
+}
+
+impl Access {
+    fn keyword(&self) -> &'static str {
+        match self {
+            Access::Scan => "scan",
+            Access::Sample(_) => "sample",
+            Access::First => "first",
+            Access::Last => "last",
+            Access::After(..) => "after",
+            Access::In(_) => "in",
+            Access::Resume(..) => "resume",
+        }
+    }
+
+    // how many select tokens spell it
+    fn n_tokens(&self) -> usize {
+        match self {
+            Access::Scan => 0,
+            Access::Sample(_) => 2,
+            Access::First | Access::Last => 1,
+            Access::After(_, limit) => 2 + limit.map_or(0, |_| 2),
+            // in ( id , id ... )
+            Access::In(ids) => 2 + 2 * ids.len(),
+            Access::Resume(_, limit) => 2 + limit.map_or(0, |_| 2),
+        }
+    }
+}
+
+impl QueryToken {
+    // placed by Query::parse, which knows where the token came from
+    fn unexpected(&self) -> SyntaxError {
+        SyntaxError {
+            message: format!("ERROR: unexpected {} in select.", self.describe()),
+            offset: None,
+        }
+    }
+    fn describe(&self) -> String {
+        match self {
+            QueryToken::Word(word) => format!("'{word}'"),
+            QueryToken::Int(int) => format!("'{int}'"),
+            QueryToken::Text(text) => format!("string '{text}'"),
+            QueryToken::Symbol(symbol) => format!("'{symbol}'"),
+        }
+    }
+}
+
+type QueryTokens = std::iter::Peekable<std::vec::IntoIter<QueryToken>>;
+
+fn next_word(tokens: &mut QueryTokens, word: &str) -> bool {
+    tokens
+        .next_if(|token| matches!(token, QueryToken::Word(next) if next == word))
+        .is_some()
+}
+
+impl Sorter {
+    fn new(descending: bool) -> Self {
+        Sorter {
+            descending,
+            buffer: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, key: Value, values: Vec<Value>) -> Result<(), Box<dyn Error>> {
+        self.buffer.push((key, values));
+        if self.buffer.len() >= SORT_RUN_ROWS {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sort_buffer(&mut self) {
+        // stable, so equal keys keep their primary key order
+        if self.descending {
+            self.buffer.sort_by(|a, b| b.0.cmp(&a.0));
+        } else {
+            self.buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+    }
+
+    // the temp file is unlinked right away and lives on through the handle
+    fn spill(&mut self) -> Result<(), Box<dyn Error>> {
+        self.sort_buffer();
+        let path =
+            env::temp_dir().join(format!("rqlite-sort-{}-{}", process::id(), self.runs.len()));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        fs::remove_file(&path)?;
+        let mut out = io::BufWriter::new(&mut file);
+        for (key, values) in self.buffer.drain(..) {
+            write_sort_value(&mut out, &key)?;
+            out.write_all(&(values.len() as u32).to_le_bytes())?;
+            for value in &values {
+                write_sort_value(&mut out, value)?;
+            }
+        }
+        out.flush()?;
+        drop(out);
+        file.rewind()?;
+        self.runs.push(file);
+        Ok(())
+    }
+
+    fn finish(mut self, mut visit: impl FnMut(Vec<Value>)) -> Result<(), Box<dyn Error>> {
+        if self.runs.is_empty() {
+            self.sort_buffer();
+            self.buffer
+                .into_iter()
+                .for_each(|(_, values)| visit(values));
+            return Ok(());
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let mut readers = self.runs.iter().map(io::BufReader::new).collect::<Vec<_>>();
+        let mut heads = readers
+            .iter_mut()
+            .map(read_sort_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        // few runs, so a linear pick of the smallest head is enough; ties go
+        // to the earlier run to keep the sort stable
+        loop {
+            let mut next: Option<usize> = None;
+            for (i, head) in heads.iter().enumerate() {
+                let Some((key, _)) = head else {
+                    continue;
+                };
+                let better = next.is_none_or(|best| {
+                    let best_key = &heads[best].as_ref().unwrap().0;
+                    if self.descending {
+                        key > best_key
+                    } else {
+                        key < best_key
+                    }
+                });
+                if better {
+                    next = Some(i);
+                }
+            }
+            let Some(i) = next else {
+                return Ok(());
+            };
+            let (_, values) = mem::replace(&mut heads[i], read_sort_row(&mut readers[i])?).unwrap();
+            visit(values);
+        }
+    }
+}
+
+fn write_sort_value(out: &mut impl Write, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Int(int) => {
+            out.write_all(&[0])?;
+            out.write_all(&int.to_le_bytes())
+        }
+        Value::Text(text) => {
+            out.write_all(&[1])?;
+            out.write_all(&(text.len() as u32).to_le_bytes())?;
+            out.write_all(text.as_bytes())
+        }
+        Value::Null => out.write_all(&[2]),
+    }
+}
+
+fn read_sort_value(input: &mut impl Read) -> io::Result<Value> {
+    let mut tag = [0; 1];
+    input.read_exact(&mut tag)?;
+    let mut word = [0; 8];
+    if tag[0] == 0 {
+        input.read_exact(&mut word)?;
+        return Ok(Value::Int(i64::from_le_bytes(word)));
+    }
+    if tag[0] == 2 {
+        return Ok(Value::Null);
+    }
+    input.read_exact(&mut word[..4])?;
+    let mut text = vec![0; u32::from_le_bytes(word[..4].try_into().unwrap()) as usize];
+    input.read_exact(&mut text)?;
+    Ok(Value::Text(String::from_utf8_lossy(&text).into_owned()))
+}
+
+// the next (key, values) of a run, None at its end
+fn read_sort_row(input: &mut impl BufRead) -> io::Result<Option<(Value, Vec<Value>)>> {
+    if input.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let key = read_sort_value(input)?;
+    let mut len = [0; 4];
+    input.read_exact(&mut len)?;
+    let values = (0..u32::from_le_bytes(len))
+        .map(|_| read_sort_value(input))
+        .collect::<io::Result<_>>()?;
+    Ok(Some((key, values)))
+}
+
+impl Expr {
+    fn parse_comparison(tokens: &mut QueryTokens) -> Result<Self, Box<dyn Error>> {
+        let left = Expr::parse(tokens)?;
+        let op = match tokens.peek() {
+            Some(QueryToken::Symbol(op @ ("=" | "!=" | "<>" | "<" | "<=" | ">" | ">="))) => *op,
+            _ => return Ok(left),
+        };
+        tokens.next();
+        let right = Expr::parse(tokens)?;
+        Ok(Expr::Compare(op, Box::new(left), Box::new(right)))
+    }
+
+    fn parse(tokens: &mut QueryTokens) -> Result<Self, Box<dyn Error>> {
+        let expr = match tokens.next() {
+            Some(QueryToken::Int(int)) => Expr::Int(int),
+            Some(QueryToken::Text(text)) => Expr::Text(text),
+            Some(QueryToken::Word(word)) => {
+                if tokens.next_if_eq(&QueryToken::Symbol("(")).is_none() {
+                    return Column::from_name(&word).map(Expr::Column);
+                }
+                let function = Function::from_name(&word)?;
+                let mut args = Vec::new();
+                if tokens.next_if_eq(&QueryToken::Symbol(")")).is_none() {
+                    loop {
+                        args.push(Expr::parse(tokens)?);
+                        match tokens.next() {
+                            Some(QueryToken::Symbol(",")) => continue,
+                            Some(QueryToken::Symbol(")")) => break,
+                            _ => return Err(format!("ERROR: unclosed {word}(.").into()),
+                        }
+                    }
+                }
+                let (min, max) = function.arity();
+                if args.len() < min || args.len() > max {
+                    return Err(format!("ERROR: wrong number of arguments to {word}().").into());
+                }
+                Expr::Call(function, args)
+            }
+            Some(token) => {
+                return Err(token.unexpected().into());
+            }
+            None => return Err("ERROR: select ends where an expression should be.".into()),
+        };
+        Ok(expr)
+    }
+
+    fn eval(&self, row: Option<&Row>, changes: &Changes) -> Result<Value, Box<dyn Error>> {
+        let value = match self {
+            Expr::Column(column) => column.value(row.ok_or("ERROR: no row to read columns of.")?),
+            Expr::Int(int) => Value::Int(*int),
+            Expr::Text(text) => Value::Text(text.clone()),
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(row, changes))
+                    .collect::<Result<Vec<_>, _>>()?;
+                function.call(&args, changes)?
+            }
+            Expr::Compare(op, left, right) => {
+                let (left, right) = (left.eval(row, changes)?, right.eval(row, changes)?);
+                // comparing with null is neither true nor false
+                if left == Value::Null || right == Value::Null {
+                    return Ok(Value::Null);
+                }
+                let result = match *op {
+                    "=" => left == right,
+                    "!=" | "<>" => left != right,
+                    "<" => left < right,
+                    "<=" => left <= right,
+                    ">" => left > right,
+                    _ => left >= right,
+                };
+                Value::Int(result as i64)
+            }
+        };
+        Ok(value)
+    }
+
+    fn uses_columns(&self) -> bool {
+        match self {
+            Expr::Column(_) => true,
+            Expr::Int(_) | Expr::Text(_) => false,
+            Expr::Call(_, args) => args.iter().any(Expr::uses_columns),
+            Expr::Compare(_, left, right) => left.uses_columns() || right.uses_columns(),
+        }
+    }
+}
+
+impl Column {
+    fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "id" => Ok(Column::Id),
+            "name" => Ok(Column::Name),
+            "description" => Ok(Column::Description),
+            _ => {
+                // the closest column, when it is near enough to be a typo
+                let suggestion = COLUMN_NAMES
+                    .iter()
+                    .map(|column| (edit_distance(name, column), column))
+                    .min()
+                    .filter(|&(distance, column)| distance <= (column.len() / 3).max(1));
+                let message = match suggestion {
+                    Some((_, column)) => {
+                        format!("ERROR: no column '{name}'; did you mean '{column}'?")
+                    }
+                    None => format!(
+                        "ERROR: no column '{name}'; the columns are {}.",
+                        COLUMN_NAMES.join(", ")
+                    ),
+                };
+                // the name was the last token taken, Query::parse places it
+                Err(SyntaxError {
+                    message,
+                    offset: None,
+                }
+                .into())
+            }
+        }
+    }
+
+    fn value(self, row: &Row) -> Value {
+        match self {
+            Column::Id => Value::Int(row.id),
+            Column::Name => Value::Text(row.name().into_owned()),
+            Column::Description => Value::Text(row.description().into_owned()),
         }
-        let new_page_index = self.table.pager.get_new_page_index();
-        let new_node = self.table.pager.get_page(new_page_index)?;
-        new_node.become_leaf_node();
-        let (old_node, new_node) = self
-            .table
-            .pager
-            .get_two_pages(self.page_index, new_page_index);
-        new_node.next_leaf = Some(old_node.next_leaf.unwrap());
-        old_node.next_leaf = Some(new_page_index as i32);
-        for i in (0..LEAF_NODE_CELL_MAX_NUM + 1).rev() {
-            let cell_index = i % SPLIT_LEFT_LEAF_NODE_NUM;
-            if i == self.cell_index {
-                if i >= SPLIT_LEFT_LEAF_NODE_NUM {
-                    new_node.put_leaf_cell(cell_index, cell.clone());
-                } else {
-                    old_node.put_leaf_cell(cell_index, cell.clone());
+    }
+}
+
+impl Function {
+    fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "length" => Ok(Function::Length),
+            "upper" => Ok(Function::Upper),
+            "lower" => Ok(Function::Lower),
+            "substr" => Ok(Function::Substr),
+            "abs" => Ok(Function::Abs),
+            "last_insert_rowid" => Ok(Function::LastInsertRowid),
+            "changes" => Ok(Function::Changes),
+            "total_changes" => Ok(Function::TotalChanges),
+            "json_extract" => Ok(Function::JsonExtract),
+            _ => Err(format!("ERROR: no function '{name}'.").into()),
+        }
+    }
+
+    // least and most arguments taken
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Function::Substr => (2, 3),
+            Function::JsonExtract => (2, 2),
+            Function::LastInsertRowid | Function::Changes | Function::TotalChanges => (0, 0),
+            _ => (1, 1),
+        }
+    }
+
+    fn call(self, args: &[Value], changes: &Changes) -> Result<Value, Box<dyn Error>> {
+        // a null goes straight through, as in sqlite
+        if args.first() == Some(&Value::Null) {
+            return Ok(Value::Null);
+        }
+        let text = || args[0].to_string();
+        let value = match self {
+            Function::Length => Value::Int(text().chars().count() as i64),
+            Function::Upper => Value::Text(text().to_uppercase()),
+            Function::Lower => Value::Text(text().to_lowercase()),
+            // 1-based like sqlite, an optional length after the start
+            Function::Substr => {
+                let int = |value: &Value| match value {
+                    Value::Int(int) => Ok(*int),
+                    Value::Text(_) | Value::Null => Err("ERROR: substr() takes integer positions."),
+                };
+                let start = (int(&args[1])? - 1).max(0) as usize;
+                let len = args
+                    .get(2)
+                    .map(int)
+                    .transpose()?
+                    .map_or(usize::MAX, |len| len.max(0) as usize);
+                Value::Text(text().chars().skip(start).take(len).collect())
+            }
+            Function::Abs => match args[0] {
+                Value::Int(int) => Value::Int(int.checked_abs().ok_or("ERROR: abs() overflow.")?),
+                Value::Text(_) | Value::Null => {
+                    return Err("ERROR: abs() takes an integer.".into());
                 }
-            } else {
-                let leaf_cells = old_node.get_mut_leaf_cells();
-                let index = if i > self.cell_index { i - 1 } else { i };
-                let cell = leaf_cells[index].take().unwrap();
-                if i >= SPLIT_LEFT_LEAF_NODE_NUM {
-                    new_node.put_leaf_cell(cell_index, cell);
-                } else {
-                    old_node.put_leaf_cell(cell_index, cell);
+            },
+            Function::LastInsertRowid => Value::Int(changes.last_insert_rowid),
+            Function::Changes => Value::Int(changes.changes as i64),
+            Function::TotalChanges => Value::Int(changes.total_changes as i64),
+            // json_extract(text, '$.key') on a flat object; numbers that fit
+            // come back as integers, a missing key as null
+            Function::JsonExtract => {
+                let path = args[1].to_string();
+                let Some(key) = path.strip_prefix("$.") else {
+                    return Err(format!(
+                        "ERROR: json_extract() takes a '$.key' path, got '{path}'."
+                    )
+                    .into());
+                };
+                let object = JsonParser::object(&text())
+                    .map_err(|message| format!("ERROR: json_extract(): {message}."))?;
+                match object.get(key).cloned().flatten() {
+                    Some(value) => match value.parse() {
+                        Ok(int) => Value::Int(int),
+                        Err(_) => Value::Text(value),
+                    },
+                    None => Value::Null,
                 }
             }
+        };
+        Ok(value)
+    }
+}
+
+impl Value {
+    fn is_true(&self) -> bool {
+        matches!(self, Value::Int(int) if *int != 0)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(int) => write!(f, "{int}"),
+            Value::Text(text) => write!(f, "{text}"),
+            Value::Null => write!(f, "NULL"),
         }
-        old_node.n_cells = SPLIT_LEFT_LEAF_NODE_NUM as u32;
-        new_node.n_cells = SPLIT_RIGHT_LEAF_NODE_NUM as u32;
-        if old_node.is_root {
-            new_node.parent = self.page_index as i32;
-            let left_child_page_index = self.table.pager.get_new_page_index();
-            let left_child = self.table.pager.get_page(left_child_page_index)?;
-            left_child.become_leaf_node();
-            let (root_node, left_child) = self
-                .table
-                .pager
-                .get_two_pages(self.page_index, left_child_page_index);
-            let n_cells = root_node.get_n_cells();
-            left_child.parent = self.page_index as i32;
-            left_child.n_cells = n_cells as u32;
-            left_child.next_leaf = Some(root_node.next_leaf.unwrap());
-            let root_leaf_cells = root_node.get_mut_leaf_cells();
-            let left_child_leaf_cells = left_child.get_mut_leaf_cells();
-            for i in 0..n_cells {
-                left_child_leaf_cells[i] = Some(root_leaf_cells[i].take().unwrap());
+    }
+}
+
+// errors that may clear up on their own get a few more tries, with a short
+// growing pause in between
+fn retry_io(mut op: impl FnMut() -> io::Result<()>) -> io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(error)
+                if attempt < IO_RETRY_MAX
+                    && matches!(
+                        error.kind(),
+                        io::ErrorKind::Interrupted
+                            | io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                    ) =>
+            {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(10 << attempt));
             }
-            root_node.become_internal_node();
-            root_node.n_cells = 1;
-            root_node.right_child = Some(new_page_index as i32);
-            let internal_cells = root_node.get_mut_internal_cells();
-            internal_cells[0] = Some(InternalCell {
-                key: left_child.get_max_key(),
-                child: left_child_page_index as i32,
+            result => return result,
+        }
+    }
+}
+
+// rqlite migrate <old database> <new database>, writes the upgraded copy
+// to a new file and leaves the old one as it is
+fn run_migrate(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [old_path, new_path] = args else {
+        return Err(UsageError.into());
+    };
+    let old = fs::read(old_path)?;
+    if old.is_empty() || !old.len().is_multiple_of(PAGE_SIZE) {
+        return Err(ERR_INVALID_FILE.into());
+    }
+    let mut pages = old
+        .chunks(PAGE_SIZE)
+        .map(|data| {
+            let mut node = Node::new();
+            node.data.copy_from_slice(data);
+            node
+        })
+        .collect::<Vec<_>>();
+    let version = pages[HEADER_PAGE_INDEX]
+        .format_version()
+        .ok_or("ERROR: not an rqlite database.")?;
+    if version < FORMAT_VERSION {
+        let first_tree_page = if version == 0 { 0 } else { ROOT_PAGE_INDEX };
+        for node in &mut pages[first_tree_page..] {
+            node.drop_parent_field();
+        }
+    }
+    match version {
+        0 => {
+            if pages.len() + 1 > PAGE_MAX_NUM {
+                return Err(ERR_TABLE_FULL.into());
+            }
+            for node in &pages {
+                NodeKind::from_u8(node.data[NODE_KIND_OFFSET])?;
+            }
+            // leaves gained a slot array with no version bump, so a headerless
+            // file may hold either layout; only a file that reads as exactly
+            // one of them is migrated
+            let leaves = pages
+                .iter()
+                .filter(|node| matches!(node.kind(), NodeKind::Leaf))
+                .collect::<Vec<_>>();
+            let slotted = leaves.iter().all(|node| {
+                node.cells_at(|i| {
+                    (node.leaf_slot(i) < node.get_n_cells()).then(|| node.leaf_cell_range(i).start)
+                })
+                .is_some()
             });
-        } else {
-            panic!("TODO: update parent after split");
+            let slotless = leaves.iter().all(|node| {
+                node.cells_at(|i| Some(LEAF_NODE_HEADER_SIZE + i * LEAF_NODE_CELL_SIZE))
+                    .is_some()
+            });
+            let has_rows = leaves.iter().any(|node| node.get_n_cells() > 0);
+            if slotted && slotless && has_rows {
+                return Err(format!(
+                    "ERROR: cannot tell whether {old_path} has slotted leaves, refusing to migrate it."
+                )
+                .into());
+            }
+            if !slotted && !slotless {
+                return Err(format!("ERROR: {old_path} has leaves in no known layout.").into());
+            }
+            for node in &mut pages {
+                if !slotted && matches!(node.kind(), NodeKind::Leaf) {
+                    let cells = node
+                        .cells_at(|i| Some(LEAF_NODE_HEADER_SIZE + i * LEAF_NODE_CELL_SIZE))
+                        .unwrap();
+                    node.data[LEAF_NODE_SLOTS_OFFSET..].fill(0);
+                    node.set_n_cells(0);
+                    for cell in &cells {
+                        node.push_leaf_cell(&cell.encode());
+                    }
+                }
+                node.shift_page_indexes(1);
+            }
+            let mut header = Node::new();
+            header.become_header_page();
+            pages.insert(HEADER_PAGE_INDEX, header);
+        }
+        1..FORMAT_VERSION => pages[HEADER_PAGE_INDEX]
+            .write_bytes(HEADER_VERSION_OFFSET, &FORMAT_VERSION.to_le_bytes()),
+        FORMAT_VERSION => {
+            return Err(format!("ERROR: {old_path} is already format version {version}.").into());
+        }
+        _ => {
+            return Err(format!(
+                "ERROR: database format version {version} is newer than {FORMAT_VERSION}, use a newer rqlite."
+            )
+            .into());
         }
-        Ok(())
     }
+    for (page_index, node) in pages.iter().enumerate() {
+        node.validate(page_index, pages.len())?;
+    }
+    let n_rows = count_rows(&pages)?;
+    pages[HEADER_PAGE_INDEX].set_n_rows(n_rows);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(new_path)?;
+    for node in &pages {
+        file.write_all(&node.data)?;
+    }
+    println!("migrated {old_path} from format version {version} to {FORMAT_VERSION}.");
+    Ok(())
 }
 
-impl NodeKind {
-    fn from_u8(v: u8) -> Result<Self, Box<dyn Error>> {
-        match v {
-            1 => Ok(Self::Internal),
-            2 => Ok(Self::Leaf),
-            _ => Err("ERROR: unkown value {v}, can't transform valid node kind.".into()),
+// the rows in the tree, for files from before the header kept the count
+fn count_rows(pages: &[Box<Node>]) -> Result<usize, Box<dyn Error>> {
+    let mut n_rows = 0;
+    let mut visited = vec![false; pages.len()];
+    let mut stack = vec![ROOT_PAGE_INDEX];
+    while let Some(page_index) = stack.pop() {
+        if mem::replace(&mut visited[page_index], true) {
+            let detail = "reached twice, the tree has a cycle";
+            return Err(CorruptionError::new(page_index, "child", detail).into());
+        }
+        let node = &pages[page_index];
+        match node.kind() {
+            NodeKind::Leaf => n_rows += node.get_n_cells(),
+            NodeKind::Internal => {
+                stack.extend((0..node.get_n_cells()).map(|i| node.internal_child(i) as usize));
+                stack.push(node.right_child() as usize);
+            }
         }
     }
-    fn to_u8(&self) -> u8 {
-        match self {
-            Self::Internal => 1,
-            Self::Leaf => 2,
+    Ok(n_rows)
+}
+
+impl History {
+    // $RQLITE_HISTORY, or ~/.rqlite_history
+    fn path() -> Option<PathBuf> {
+        env::var_os("RQLITE_HISTORY")
+            .map(PathBuf::from)
+            .or_else(|| Some(Path::new(&env::var_os("HOME")?).join(".rqlite_history")))
+    }
+
+    fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        // a line cut short by a crash is skipped rather than failing the repl
+        let entries = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let time = fields.next()?.parse().ok()?;
+                let ok = fields.next()? == "ok";
+                let statement = fields.next()?.to_string();
+                Some(HistoryEntry {
+                    time,
+                    ok,
+                    statement,
+                })
+            })
+            .collect();
+        Ok(History { file, entries })
+    }
+
+    // failing to write the history is reported but never fails the statement
+    fn record(history: &mut Option<History>, statement: &str, ok: bool) {
+        let Some(history) = history else {
+            return;
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let status = if ok { "ok" } else { "error" };
+        if let Err(error) = writeln!(history.file, "{time}\t{status}\t{statement}") {
+            eprintln!("ERROR: history not saved: {error}");
+        }
+        history.entries.push(HistoryEntry {
+            time,
+            ok,
+            statement: statement.to_string(),
+        });
+    }
+
+    fn get(history: &Option<History>, n: &str) -> Result<String, Box<dyn Error>> {
+        let history = history.as_ref().ok_or("ERROR: history is off.")?;
+        n.parse::<usize>()
+            .ok()
+            .and_then(|n| history.entries.get(n.checked_sub(1)?))
+            .map(|entry| entry.statement.clone())
+            .ok_or_else(|| format!("ERROR: no statement {n} in the history.").into())
+    }
+
+    // the last n statements, numbered for !n
+    fn print(&self, n: usize) {
+        let start = self.entries.len().saturating_sub(n);
+        for (i, entry) in self.entries.iter().enumerate().skip(start) {
+            let status = if entry.ok { "ok" } else { "error" };
+            let time = format_utc(entry.time);
+            println!("{:>5}  {time}  {status:<5}  {}", i + 1, entry.statement);
         }
     }
 }
 
-impl Node {
-    fn become_leaf_node(&mut self) {
-        self.kind = NodeKind::Leaf;
-        self.n_cells = 0;
-        self.next_leaf = Some(NOT_EXIST);
-        self.leaf_cells = Some([const { None }; LEAF_NODE_CELL_MAX_NUM]);
-        self.right_child = None;
-        self.internal_cells = None;
+// unix seconds as yyyy-mm-dd hh:mm:ss in UTC, using the days-to-civil
+// conversion from Howard Hinnant's date algorithms
+fn format_utc(secs: u64) -> String {
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// explain analyze <statement>: runs the statement after planning it and
+// reports the time each step took along with the rows and pages it touched;
+// a select counts its rows instead of printing them
+fn explain_analyze(
+    table: &mut Table,
+    input: &str,
+    args: &[&str],
+) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+    let keyword = args[0];
+    if keyword != "select" && table.dry_run {
+        return Err(
+            format!("ERROR: explain analyze runs the {keyword}, turn dry run off first.").into(),
+        );
     }
-    fn become_internal_node(&mut self) {
-        self.kind = NodeKind::Internal;
-        self.n_cells = 0;
-        self.next_leaf = None;
-        self.leaf_cells = None;
-        self.right_child = Some(NOT_EXIST);
-        self.internal_cells = Some([const { None }; INTERNAL_NODE_CELL_MAX_NUM]);
+    if keyword == "insert" && table.batch.is_some() {
+        return Err(
+            "ERROR: explain analyze runs the insert, apply or discard the batch first.".into(),
+        );
     }
-    fn get_n_cells(&self) -> usize {
-        self.n_cells as usize
-    }
-    fn get_mut_leaf_cells(&mut self) -> &mut [Option<LeafCell>] {
-        self.leaf_cells
-            .as_mut()
-            .map(|arr| arr.as_mut_slice())
-            .expect("ERROR: get_mut_leaf_cells must be called by leaf node.")
-    }
-    fn get_mut_internal_cells(&mut self) -> &mut [Option<InternalCell>] {
-        self.internal_cells
-            .as_mut()
-            .map(|arr| arr.as_mut_slice())
-            .expect("ERROR: get_mut_internal_cells must be called by internal node.")
-    }
-    fn read_at(file: &File, mut offset: usize) -> Result<Self, Box<dyn Error>> {
-        let mut kind_buf = [0u8; NODE_KIND_SIZE];
-        let mut is_root_buf = [0u8; NODE_IS_ROOT_SIZE];
-        let mut parent_buf = [0u8; NODE_PARENT_SIZE];
-        let mut n_cells_buf = [0u8; NODE_N_CELLS_SIZE];
-        read_and_advance(file, &mut kind_buf, &mut offset, NODE_KIND_SIZE)?;
-        read_and_advance(file, &mut is_root_buf, &mut offset, NODE_IS_ROOT_SIZE)?;
-        read_and_advance(file, &mut parent_buf, &mut offset, NODE_PARENT_SIZE)?;
-        read_and_advance(file, &mut n_cells_buf, &mut offset, NODE_N_CELLS_SIZE)?;
-        let mut new_node = Node {
-            kind: NodeKind::from_u8(u8::from_le_bytes(kind_buf))?,
-            is_root: u8::from_le_bytes(is_root_buf) != 0,
-            parent: i32::from_le_bytes(parent_buf),
-            n_cells: u32::from_le_bytes(n_cells_buf),
-            next_leaf: None,
-            leaf_cells: None,
-            right_child: None,
-            internal_cells: None,
-        };
-        if let NodeKind::Internal = new_node.kind {
-            let mut right_child_buf = [0u8; INTERNAL_NODE_RIGHT_CHILD_SIZE];
-            read_and_advance(
-                file,
-                &mut right_child_buf,
-                &mut offset,
-                INTERNAL_NODE_RIGHT_CHILD_SIZE,
-            )?;
-            new_node.right_child = Some(i32::from_le_bytes(right_child_buf));
-            new_node.internal_cells = Some([const { None }; INTERNAL_NODE_CELL_MAX_NUM]);
-            let n_cells = new_node.get_n_cells();
-            for cell in new_node.get_mut_internal_cells().iter_mut().take(n_cells) {
-                let mut internal_cell_key_buf = [0u8; INTERNAL_NODE_CELL_KEY_SIZE];
-                let mut internal_cell_child_buf = [0u8; INTERNAL_NODE_CELL_CHILD_SIZE];
-                read_and_advance(
-                    file,
-                    &mut internal_cell_child_buf,
-                    &mut offset,
-                    INTERNAL_NODE_CELL_CHILD_SIZE,
-                )?;
-                read_and_advance(
-                    file,
-                    &mut internal_cell_key_buf,
-                    &mut offset,
-                    INTERNAL_NODE_CELL_KEY_SIZE,
-                )?;
-                *cell = Some(InternalCell {
-                    key: i64::from_le_bytes(internal_cell_key_buf),
-                    child: i32::from_le_bytes(internal_cell_child_buf),
-                });
-            }
-            return Ok(new_node);
+    let start = Instant::now();
+    let query = match keyword {
+        "select" => {
+            let text = text_after(input, keyword);
+            let query =
+                Query::parse(text).map_err(|error| point_at_syntax_error(input, text, error));
+            Some(query?)
         }
-        let mut next_leaf_buf = [0u8; LEAF_NODE_NEXT_LEAF_SIZE];
-        read_and_advance(
-            file,
-            &mut next_leaf_buf,
-            &mut offset,
-            LEAF_NODE_NEXT_LEAF_SIZE,
-        )?;
-        new_node.next_leaf = Some(i32::from_le_bytes(next_leaf_buf));
-        new_node.leaf_cells = Some([const { None }; LEAF_NODE_CELL_MAX_NUM]);
-        let n_cells = new_node.get_n_cells();
-        for cell in new_node.get_mut_leaf_cells().iter_mut().take(n_cells) {
-            let mut leaf_cell_key_buf = [0u8; LEAF_NODE_CELL_KEY_SIZE];
-            read_and_advance(
-                file,
-                &mut leaf_cell_key_buf,
-                &mut offset,
-                LEAF_NODE_CELL_KEY_SIZE,
-            )?;
-            let mut id_buf = [0u8; ID_SIZE];
-            let mut name_buf = [0u8; NAME_MAX_SIZE];
-            let mut description_buf = [0u8; DESCRIPTION_MAX_SIZE];
-            read_and_advance(file, &mut id_buf, &mut offset, ID_SIZE)?;
-            read_and_advance(file, &mut name_buf, &mut offset, NAME_MAX_SIZE)?;
-            read_and_advance(
-                file,
-                &mut description_buf,
-                &mut offset,
-                DESCRIPTION_MAX_SIZE,
-            )?;
-            *cell = Some(LeafCell {
-                key: i64::from_le_bytes(leaf_cell_key_buf),
-                value: Row {
-                    id: i64::from_le_bytes(id_buf),
-                    name: name_buf,
-                    description: description_buf,
-                },
-            });
+        "insert" => {
+            table.parse_row(&args[1..], false)?;
+            None
+        }
+        "analyze" => None,
+        _ => return Err(format!("ERROR: cannot explain analyze '{keyword}'.").into()),
+    };
+    let parse_time = start.elapsed();
+    let plan = table.explain(args)?;
+    let plan_time = start.elapsed() - parse_time;
+    let stats = &table.pager.stats;
+    let stats_before = (
+        stats.pages_read,
+        stats.read_calls,
+        stats.cache_hits,
+        stats.io_time,
+    );
+    let rows_examined = table.rows_examined;
+    let mut n_rows = 0;
+    let result = match query {
+        Some(query) => {
+            table.select_into(&query, |_| n_rows += 1)?;
+            format!("rows returned: {n_rows}")
         }
-        Ok(new_node)
+        None if keyword == "insert" => {
+            table.insert(&args[1..])?;
+            "rows written: 1".to_string()
+        }
+        None => format!("rows analyzed: {}", table.analyze()?.n_rows),
+    };
+    let execute_time = start.elapsed() - parse_time - plan_time;
+    let stats = &table.pager.stats;
+    let mut analysis = vec![
+        format!("parse: {parse_time:?}"),
+        format!("plan: {plan_time:?}"),
+        format!("execute: {execute_time:?}"),
+        format!(
+            "  pager: {} pages read in {} calls, {} cache hits, {:?} in i/o",
+            stats.pages_read - stats_before.0,
+            stats.read_calls - stats_before.1,
+            stats.cache_hits - stats_before.2,
+            stats.io_time - stats_before.3
+        ),
+        format!(
+            "  scan: {} rows examined",
+            table.rows_examined - rows_examined
+        ),
+    ];
+    if let Some(sort_time) = table.sort_time {
+        analysis.push(format!("  sort: {sort_time:?}"));
     }
-    fn write_at(&mut self, mut file: &File, mut offset: usize) -> Result<(), Box<dyn Error>> {
-        let start = offset;
-        write_and_advance(
-            file,
-            &self.kind.to_u8().to_le_bytes(),
-            &mut offset,
-            NODE_KIND_SIZE,
-        )?;
-        write_and_advance(
-            file,
-            &(self.is_root as u8).to_le_bytes(),
-            &mut offset,
-            NODE_IS_ROOT_SIZE,
-        )?;
-        write_and_advance(
-            file,
-            &self.parent.to_le_bytes(),
-            &mut offset,
-            NODE_PARENT_SIZE,
-        )?;
-        let n_cells = self.get_n_cells() as u32;
-        write_and_advance(file, &n_cells.to_le_bytes(), &mut offset, NODE_N_CELLS_SIZE)?;
-        if let NodeKind::Internal = self.kind {
-            write_and_advance(
-                file,
-                &self.right_child.unwrap().to_le_bytes(),
-                &mut offset,
-                INTERNAL_NODE_RIGHT_CHILD_SIZE,
-            )?;
-            for cell in self
-                .get_mut_internal_cells()
-                .iter()
-                .take(n_cells as usize)
-                .flatten()
-            {
-                write_and_advance(
-                    file,
-                    &cell.child.to_le_bytes(),
-                    &mut offset,
-                    INTERNAL_NODE_CELL_CHILD_SIZE,
-                )?;
-                write_and_advance(
-                    file,
-                    &cell.key.to_le_bytes(),
-                    &mut offset,
-                    INTERNAL_NODE_CELL_KEY_SIZE,
-                )?;
-            }
-            let padding_len = PAGE_SIZE - (offset - start);
-            let padding = vec![0u8; padding_len];
-            write_and_advance(file, &padding, &mut offset, padding_len)?;
-            file.flush()?;
-            return Ok(());
+    analysis.push(result);
+    analysis.push(format!("total: {:?}", start.elapsed()));
+    Ok((plan, analysis))
+}
+
+// .watch <seconds> [--count N] select ...: runs the select again every
+// interval, rereading the file each round so rows other processes write
+// show up; without a count it runs until the repl is killed
+fn watch(table: &mut Table, input: &str, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let (interval, args) = args.split_first().ok_or(ERR_WATCH_SYNTAX)?;
+    let interval = interval
+        .parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or(ERR_WATCH_SYNTAX)?;
+    let (count, args) = match args {
+        ["--count", n, args @ ..] => (
+            Some(n.parse::<usize>().map_err(|_| ERR_WATCH_SYNTAX)?),
+            args,
+        ),
+        _ => (None, args),
+    };
+    let Some(&keyword @ "select") = args.first() else {
+        return Err(ERR_WATCH_SYNTAX.into());
+    };
+    let text = text_after(input, keyword);
+    let query = Query::parse(text).map_err(|error| point_at_syntax_error(input, text, error))?;
+    let statement = &input[keyword.as_ptr() as usize - input.as_ptr() as usize..];
+    let clear_screen = io::stdout().is_terminal();
+    for round in 0.. {
+        if count.is_some_and(|count| round >= count) {
+            break;
         }
-        write_and_advance(
-            file,
-            &self.next_leaf.unwrap().to_le_bytes(),
-            &mut offset,
-            LEAF_NODE_NEXT_LEAF_SIZE,
-        )?;
-        for cell in self
-            .get_mut_leaf_cells()
-            .iter()
-            .take(n_cells as usize)
-            .flatten()
-        {
-            write_and_advance(
-                file,
-                &cell.key.to_le_bytes(),
-                &mut offset,
-                LEAF_NODE_CELL_KEY_SIZE,
-            )?;
-            write_and_advance(file, &cell.value.id.to_le_bytes(), &mut offset, ID_SIZE)?;
-            write_and_advance(file, &cell.value.name, &mut offset, NAME_MAX_SIZE)?;
-            write_and_advance(
-                file,
-                &cell.value.description,
-                &mut offset,
-                DESCRIPTION_MAX_SIZE,
-            )?;
-        }
-        let padding_len = PAGE_SIZE - (offset - start);
-        let padding = vec![0u8; padding_len];
-        write_and_advance(file, &padding, &mut offset, padding_len)?;
-        file.flush()?;
-        Ok(())
+        if round > 0 {
+            thread::sleep(interval);
+        }
+        table.reload()?;
+        table.statement_start = Instant::now();
+        if clear_screen {
+            print!("\x1b[2J\x1b[H");
+        }
+        println!("every {}s: {statement}", interval.as_secs_f64());
+        table.select(&query)?;
+    }
+    Ok(())
+}
+
+// .open --as <alias> <database> keeps another database open next to the main
+// one, and .close <alias> flushes it and lets it go
+fn attach(
+    attached: &mut BTreeMap<String, Table>,
+    main_table: &Table,
+    args: &[&str],
+) -> Result<String, Box<dyn Error>> {
+    match *args {
+        [".open", "--as", alias, path] => {
+            if alias.starts_with('.') || STATEMENT_KEYWORDS.contains(&alias) {
+                return Err(format!("ERROR: '{alias}' is a keyword, pick another alias.").into());
+            }
+            if attached.contains_key(alias) {
+                return Err(format!("ERROR: '{alias}' is already in use.").into());
+            }
+            let mut table = Table::new(Pager::new(path)?);
+            // two pagers on one file would overwrite each other's pages
+            let id = |table: &Table| {
+                let metadata = table.pager.file.metadata()?;
+                io::Result::Ok((metadata.dev(), metadata.ino()))
+            };
+            let new_id = id(&table)?;
+            for other in attached.values().chain([main_table]) {
+                if id(other)? == new_id {
+                    table.closed = true;
+                    return Err(format!("ERROR: {path} is already open.").into());
+                }
+            }
+            table.timeout = main_table.timeout;
+            table.slow = main_table.slow;
+            table.dry_run = main_table.dry_run;
+            attached.insert(alias.to_string(), table);
+            Ok(format!("opened {path} as {alias}."))
+        }
+        [".close", alias] => match attached.remove(alias) {
+            Some(table) => {
+                table.close()?;
+                Ok(format!("closed {alias}."))
+            }
+            None => Err(format!("ERROR: no database is open as '{alias}'.").into()),
+        },
+        [".open", ..] => Err(ERR_OPEN_SYNTAX.into()),
+        _ => Err(ERR_CLOSE_SYNTAX.into()),
+    }
+}
+
+// min, median, 90th percentile and max of sorted lengths, by nearest rank
+fn describe_lens(lens: &[usize], max_size: usize) -> String {
+    if lens.is_empty() {
+        return "no rows".to_string();
     }
-    fn read_leaf_cell(&self, cell_index: usize) -> Option<&LeafCell> {
-        self.leaf_cells.as_ref()?.get(cell_index)?.as_ref()
+    let rank = |percent: usize| lens[(lens.len() * percent).div_ceil(100) - 1];
+    format!(
+        "min {}, p50 {}, p90 {}, max {} of {max_size}",
+        lens[0],
+        rank(50),
+        rank(90),
+        lens[lens.len() - 1]
+    )
+}
+
+// pages a full rebuild would take for n_rows, every page filled like a bulk load
+fn min_tree_pages(n_rows: usize) -> usize {
+    let leaf_fill = (LEAF_NODE_CELL_MAX_NUM * BULK_LOAD_FILL_PERCENT / 100).max(1);
+    let mut n_nodes = n_rows.div_ceil(leaf_fill).max(1);
+    let mut n_pages = n_nodes;
+    while n_nodes > 1 {
+        n_nodes = n_nodes.div_ceil(INTERNAL_NODE_CELL_MAX_NUM + 1);
+        n_pages += n_nodes;
+    }
+    n_pages
+}
+
+fn print_tree_levels(levels: &[TreeLevel]) {
+    println!("ANALYZE TREE:");
+    println!("depth: {}", levels.len());
+    for (depth, level) in levels.iter().enumerate() {
+        let (kind, capacity) = match level.kind {
+            NodeKind::Internal => ("internal", INTERNAL_NODE_CELL_MAX_NUM + 1),
+            NodeKind::Leaf => ("leaf", LEAF_NODE_CELL_MAX_NUM),
+        };
+        let capacity = level.n_pages * capacity;
+        let entries = match level.kind {
+            NodeKind::Internal => format!(
+                "fan-out {:.1}, children {} of {capacity}",
+                level.n_entries as f64 / level.n_pages as f64,
+                level.n_entries
+            ),
+            NodeKind::Leaf => format!("cells {} of {capacity}", level.n_entries),
+        };
+        println!(
+            "level {} {kind}: pages {}, {entries} ({}%)",
+            depth + 1,
+            level.n_pages,
+            level.n_entries * 100 / capacity
+        );
     }
-    fn read_internal_cell(&self, cell_index: usize) -> Option<&InternalCell> {
-        self.internal_cells.as_ref()?.get(cell_index)?.as_ref()
+    let n_rows = levels.last().map_or(0, |level| level.n_entries);
+    let n_pages = levels.iter().map(|level| level.n_pages).sum::<usize>();
+    let min_pages = min_tree_pages(n_rows);
+    println!("tree pages: {n_pages}, a full rebuild needs {min_pages}");
+    if n_pages > min_pages {
+        let saved = n_pages - min_pages;
+        println!("pages .optimize would save: {saved}");
     }
-    fn put_leaf_cell(&mut self, cell_index: usize, cell: LeafCell) {
-        let leaf_cells = self.leaf_cells.as_mut().unwrap();
-        leaf_cells[cell_index] = Some(cell);
+}
+
+fn print_profile(profile: &Profile) {
+    let text_size = NAME_MAX_SIZE + DESCRIPTION_MAX_SIZE;
+    let n_rows = profile.name_lens.len();
+    let text_bytes = profile
+        .leaves
+        .iter()
+        .map(|&(_, _, bytes)| bytes)
+        .sum::<usize>();
+    println!("PROFILE:");
+    println!("rows: {n_rows}");
+    println!(
+        "name length: {}",
+        describe_lens(&profile.name_lens, NAME_MAX_SIZE)
+    );
+    println!(
+        "description length: {}",
+        describe_lens(&profile.description_lens, DESCRIPTION_MAX_SIZE)
+    );
+    println!("padding bytes: {}", n_rows * text_size - text_bytes);
+    for &(page_index, n_cells, text_bytes) in &profile.leaves {
+        let text_percent = match n_cells {
+            0 => 0,
+            _ => text_bytes * 100 / (n_cells * text_size),
+        };
+        println!(
+            "leaf page {page_index}: {n_cells} of {LEAF_NODE_CELL_MAX_NUM} cells, {text_percent}% of text space used"
+        );
     }
-    fn insert_leaf_cell(&mut self, cell_index: usize, cell: LeafCell) {
-        let n_cells = self.get_n_cells();
-        let mut i = n_cells;
-        let leaf_cells = self.get_mut_leaf_cells();
-        while cell_index < i {
-            leaf_cells[i] = leaf_cells[i - 1].take();
-            i -= 1;
+}
+
+// the most tokens each command takes, its keyword included
+fn max_tokens(keyword: &str) -> Option<usize> {
+    match keyword {
+        "insert" => Some(4),
+        "analyze" | "truncate" => Some(1),
+        "create" => Some(5),
+        "exists" => Some(2),
+        ".recover" | ".snapshot" | ".timeout" | ".dryrun" | ".history" | ".batch" => Some(2),
+        ".close" | ".profile" => Some(2),
+        ".open" => Some(4),
+        ".export" => Some(3),
+        ".exit" | ".flush" | ".constants" | ".tree" | ".stats" | ".dbinfo" => Some(1),
+        ".optimize" => Some(1),
+        ".analyze_tree" => Some(1),
+        _ => None,
+    }
+}
+
+fn parse_ms(ms: &str) -> Result<Duration, Box<dyn Error>> {
+    let ms = ms
+        .parse::<u64>()
+        .map_err(|_| format!("ERROR: '{ms}' is not a number of milliseconds."))?;
+    Ok(Duration::from_millis(ms))
+}
+
+// milliseconds, 0 for no timeout
+fn parse_timeout(ms: &str) -> Result<Option<Duration>, Box<dyn Error>> {
+    Ok(Some(parse_ms(ms)?).filter(|timeout| !timeout.is_zero()))
+}
+
+// rqlite [--timeout <ms>] [--slow-ms <ms>] <database>
+fn parse_repl_args(args: &[String]) -> Result<ReplOptions<'_>, Box<dyn Error>> {
+    let (mut timeout, mut slow, mut dry_run) = (None, None, false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(UsageError);
+        match arg.as_str() {
+            "--timeout" => timeout = parse_timeout(value()?)?,
+            "--slow-ms" => slow = Some(parse_ms(value()?)?),
+            "--dry-run" => dry_run = true,
+            path if !path.starts_with("--") && args.len() == 0 => {
+                return Ok(ReplOptions {
+                    path,
+                    timeout,
+                    slow,
+                    dry_run,
+                });
+            }
+            _ => break,
         }
-        leaf_cells[cell_index] = Some(cell);
-        self.n_cells = (n_cells as u32) + 1;
     }
-    fn get_max_key(&self) -> i64 {
-        let index = self.get_n_cells() - 1;
-        match self.kind {
-            NodeKind::Leaf => self.read_leaf_cell(index).unwrap().key,
-            NodeKind::Internal => self.read_internal_cell(index).unwrap().key,
+    Err(UsageError.into())
+}
+
+// the rest of the input line from `token` on, which must be a slice of it
+fn text_after<'a>(input: &'a str, token: &str) -> &'a str {
+    let offset = token.as_ptr() as usize - input.as_ptr() as usize;
+    &input[offset + token.len()..]
+}
+
+// rejects anything past what the command takes, pointing at the first extra
+// token under the input line
+fn check_trailing_tokens(input: &str, tokens: &[&str]) -> Result<(), Box<dyn Error>> {
+    let Some(&keyword) = tokens.first() else {
+        return Ok(());
+    };
+    let Some(&extra) = max_tokens(keyword).and_then(|max| tokens.get(max)) else {
+        return Ok(());
+    };
+    // tokens are slices of input, so the pointer distance is the byte offset
+    let offset = extra.as_ptr() as usize - input.as_ptr() as usize;
+    let message = format!("ERROR: unexpected '{extra}' after {keyword}.");
+    Err(point_at(&message, input, offset).into())
+}
+
+// the message over the input line, with a caret under the byte at offset
+fn point_at(message: &str, input: &str, offset: usize) -> String {
+    let column = input[..offset].chars().count();
+    format!("{message}\n{input}\n{}^", " ".repeat(column))
+}
+
+// a SyntaxError from parsing `text`, a slice of input, pointed at in input
+fn point_at_syntax_error(input: &str, text: &str, error: Box<dyn Error>) -> Box<dyn Error> {
+    match error.downcast_ref::<SyntaxError>() {
+        Some(SyntaxError {
+            message,
+            offset: Some(offset),
+        }) => {
+            let start = text.as_ptr() as usize - input.as_ptr() as usize;
+            point_at(message, input, start + offset).into()
         }
+        _ => error,
     }
-    fn get_child_page_index(&self, cell_index: usize) -> usize {
-        match self.kind {
-            NodeKind::Leaf => panic!("ERROR: get_child_page_index must be called by internal node."),
-            NodeKind::Internal => {
-                let n_cells = self.get_n_cells();
-                if cell_index > n_cells {
-                    panic!("cell_index out of bound");
-                } else if cell_index == n_cells {
-                    self.right_child.unwrap() as usize
-                } else {
-                    let leaf_cell = self.read_internal_cell(cell_index);
-                    leaf_cell.unwrap().child as usize
+}
+
+// edits turning one name into the other, counted in chars: inserts,
+// deletes, substitutions and swaps of neighbours
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            d[i][j] = if i == 0 || j == 0 {
+                i + j
+            } else {
+                let substitution = d[i - 1][j - 1] + (a[i - 1] != b[j - 1]) as usize;
+                let mut distance = substitution.min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                    distance = distance.min(d[i - 2][j - 2] + 1);
                 }
-            }
+                distance
+            };
         }
     }
+    d[a.len()][b.len()]
 }
 
-fn write_and_advance(
-    file: &File,
-    buf: &[u8],
-    offset: &mut usize,
-    advance_distance: usize,
-) -> Result<(), Box<dyn Error>> {
-    file.write_at(buf, *offset as u64)?;
-    *offset += advance_distance;
-    Ok(())
+// the sequence in an insert id written as nextval('<name>')
+fn nextval_name(arg: &str) -> Option<&str> {
+    arg.strip_prefix("nextval('")?.strip_suffix("')")
 }
 
-fn read_and_advance(
-    file: &File,
-    buf: &mut [u8],
-    offset: &mut usize,
-    advance_distance: usize,
-) -> Result<(), Box<dyn Error>> {
-    file.read_at(buf, *offset as u64)?;
-    *offset += advance_distance;
-    Ok(())
+// prefix an import error with where it happened, e.g. "line 3"
+fn import_error(position: String, error: Box<dyn Error>) -> Box<dyn Error> {
+    let message = error.to_string();
+    let message = message.trim_start_matches("ERROR: ");
+    format!("ERROR: {position}: {message}").into()
+}
+
+fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    if error.is::<CorruptionError>() {
+        EXIT_CORRUPT
+    } else if error.is::<UsageError>() {
+        EXIT_USAGE
+    } else {
+        EXIT_ERROR
+    }
 }
 
 fn print_with_indentation(indentation: usize, text: &str) {
@@ -769,22 +5454,63 @@ fn print_with_indentation(indentation: usize, text: &str) {
 
 fn main() {
     let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("USAGE: rqlite <database>");
-        process::exit(1);
+    let run: Option<Subcommand> = match args.get(1).map(String::as_str) {
+        Some("bench") => Some(run_bench),
+        Some("recover") => Some(run_recover),
+        Some("diff") => Some(run_diff),
+        Some("dump") => Some(run_dump),
+        Some("restore") => Some(run_restore),
+        Some("verify") => Some(run_verify),
+        Some("stats") => Some(run_stats),
+        Some("migrate") => Some(run_migrate),
+        _ => None,
+    };
+    if let Some(run) = run {
+        if let Err(error) = run(&args[2..]) {
+            eprintln!("{error}");
+            process::exit(exit_code(&*error));
+        }
+        return;
     }
-    let pager = Pager::new(&args[1]).unwrap_or_else(|error| {
-        eprintln!("ERROR: init pager: {error}.");
-        process::exit(1);
+    let ReplOptions {
+        path,
+        timeout,
+        slow,
+        dry_run,
+    } = parse_repl_args(&args[1..]).unwrap_or_else(|error| {
+        eprintln!("{error}");
+        process::exit(EXIT_USAGE);
+    });
+    let pager = Pager::new(path).unwrap_or_else(|error| {
+        eprintln!("ERROR: init pager: {error}");
+        process::exit(exit_code(&*error));
+    });
+    let mut main_table = Table::new(pager);
+    main_table.timeout = timeout;
+    main_table.slow = slow;
+    main_table.dry_run = dry_run;
+    // databases opened with .open --as, by alias
+    let mut attached = BTreeMap::new();
+    let mut history = History::path().and_then(|path| {
+        History::open(&path)
+            .inspect_err(|error| eprintln!("ERROR: history off: {error}"))
+            .ok()
     });
-    let mut table = Table::new(pager);
     let mut buf = String::new();
+    // query results go to stdout, the prompt and errors to stderr, and the
+    // worst error seen decides the exit code
+    let mut status = 0;
+    let n_errors = Cell::new(0);
+    let mut report = |error: Box<dyn Error>| {
+        eprintln!("{error}");
+        status = status.max(exit_code(&*error));
+        n_errors.set(n_errors.get() + 1);
+    };
     loop {
-        print!("rqlite> ");
-        io::stdout().flush().expect("ERROR: flush.");
+        eprint!("rqlite> ");
         let n = io::stdin().read_line(&mut buf).unwrap_or_else(|error| {
             eprintln!("ERROR: read_line fail: {error}.");
-            process::exit(1);
+            process::exit(EXIT_ERROR);
         });
         if n == 0 {
             break;
@@ -793,10 +5519,183 @@ fn main() {
         if input.is_empty() {
             continue;
         }
+        // !n runs statement n of .history again, echoing it first
+        if let Some(n) = input.strip_prefix('!') {
+            match History::get(&history, n) {
+                Ok(statement) => {
+                    eprintln!("{statement}");
+                    buf = statement;
+                }
+                Err(e) => {
+                    report(e);
+                    buf.clear();
+                    continue;
+                }
+            }
+        }
+        let input = buf.trim();
+        let errors_before = n_errors.get();
+        if let Some(".open" | ".close") = input.split_whitespace().next() {
+            let args = input.split_whitespace().collect::<Vec<_>>();
+            match check_trailing_tokens(input, &args)
+                .and_then(|()| attach(&mut attached, &main_table, &args))
+            {
+                Ok(message) => println!("{message}"),
+                Err(e) => report(e),
+            }
+            History::record(&mut history, input, n_errors.get() == errors_before);
+            buf.clear();
+            continue;
+        }
+        // .profile names its table rather than taking an alias prefix
+        if let Some(".profile") = input.split_whitespace().next() {
+            let args = input.split_whitespace().collect::<Vec<_>>();
+            if let Err(e) = check_trailing_tokens(input, &args) {
+                report(e);
+                History::record(&mut history, input, false);
+                buf.clear();
+                continue;
+            }
+            let table = match args[1..] {
+                [] | ["main"] => Ok(&mut main_table),
+                [alias] => attached
+                    .get_mut(alias)
+                    .ok_or_else(|| format!("ERROR: no table named '{alias}'.")),
+                _ => Err(ERR_PROFILE_SYNTAX.to_string()),
+            };
+            match table.map_err(Into::into).and_then(|table| table.profile()) {
+                Ok(profile) => print_profile(&profile),
+                Err(e) => report(e),
+            }
+            History::record(&mut history, input, n_errors.get() == errors_before);
+            buf.clear();
+            continue;
+        }
+        let line = input;
+        // a statement prefixed by an alias runs on that database instead
+        let (table, input) = match input.split_once([' ', '\t']) {
+            Some((alias, rest)) if attached.contains_key(alias) => {
+                (attached.get_mut(alias).unwrap(), rest.trim_start())
+            }
+            _ => (&mut main_table, input),
+        };
+        if !input.starts_with(".") || input.starts_with(".import") {
+            table.pager.stats = PagerStats::default();
+        }
+        let tokens = input
+            .split([' ', '\t'])
+            .filter(|token| !token.is_empty())
+            .collect::<Vec<_>>();
+        let statement = match tokens[..] {
+            ["explain", "analyze", _, ..] => &tokens[2..],
+            ["explain", ..] => &tokens[1..],
+            _ => &tokens[..],
+        };
+        if let Err(e) = check_trailing_tokens(input, statement) {
+            report(e);
+            History::record(&mut history, line, false);
+            buf.clear();
+            continue;
+        }
+        // the plan is worked out up front, before the statement changes the
+        // tree, and left out of the pages read
+        let plan = match (table.slow, tokens[0]) {
+            (Some(_), "insert" | "select" | "analyze") => table.explain(&tokens).ok(),
+            _ => None,
+        };
+        if table.dry_run
+            && matches!(
+                tokens[0],
+                "analyze" | "truncate" | "create" | ".import" | ".optimize"
+            )
+        {
+            report(
+                format!(
+                    "ERROR: {} writes to the database, turn dry run off first.",
+                    tokens[0]
+                )
+                .into(),
+            );
+            History::record(&mut history, line, false);
+            buf.clear();
+            continue;
+        }
+        let stats_before = (table.pager.stats.pages_read, table.pager.stats.cache_hits);
+        table.statement_start = Instant::now();
+        table.rows_examined = 0;
+        table.sort_time = None;
         if input.starts_with(".") {
             // exec metacommand
-            match input {
-                ".exit" => break,
+            match tokens[0] {
+                // the repl stays open when the flush fails, so space can be
+                // freed and .exit tried again
+                ".exit" => match table.flush() {
+                    Ok(()) => break,
+                    Err(e) => report(e),
+                },
+                ".flush" => match table.flush() {
+                    Ok(()) => println!("flushed."),
+                    Err(e) => report(e),
+                },
+                ".timeout" => match tokens[1..] {
+                    [] => match table.timeout {
+                        Some(timeout) => println!("timeout: {} ms.", timeout.as_millis()),
+                        None => println!("timeout: off."),
+                    },
+                    [ms] => match parse_timeout(ms) {
+                        Ok(timeout) => table.timeout = timeout,
+                        Err(e) => report(e),
+                    },
+                    _ => unreachable!(),
+                },
+                ".history" => match &history {
+                    Some(history) => match tokens[1..] {
+                        [] => history.print(history.entries.len()),
+                        [n] => match n.parse() {
+                            Ok(n) => history.print(n),
+                            Err(_) => report(ERR_HISTORY_SYNTAX.into()),
+                        },
+                        _ => unreachable!(),
+                    },
+                    None => report("ERROR: history is off.".into()),
+                },
+                ".watch" => {
+                    if let Err(e) = watch(table, input, &tokens[1..]) {
+                        report(e);
+                    }
+                }
+                ".batch" => match tokens[1..] {
+                    [] => match &table.batch {
+                        Some(batch) => println!("batch: {} rows queued.", batch.rows.len()),
+                        None => println!("batch: none."),
+                    },
+                    ["begin"] if table.batch.is_some() => {
+                        report("ERROR: a batch is already open.".into())
+                    }
+                    ["begin"] => table.batch = Some(WriteBatch::default()),
+                    ["apply"] if table.dry_run => report(
+                        "ERROR: .batch apply writes to the database, turn dry run off first."
+                            .into(),
+                    ),
+                    ["apply"] => match table.batch.take() {
+                        Some(batch) => match table.apply(batch) {
+                            Ok(n_rows) => println!("applied {n_rows} rows."),
+                            Err(e) => report(e),
+                        },
+                        None => report(ERR_NO_BATCH.into()),
+                    },
+                    ["discard"] => match table.batch.take() {
+                        Some(batch) => println!("discarded {} rows.", batch.rows.len()),
+                        None => report(ERR_NO_BATCH.into()),
+                    },
+                    _ => report(ERR_BATCH_SYNTAX.into()),
+                },
+                ".dryrun" => match tokens[1..] {
+                    [] => println!("dry run: {}.", if table.dry_run { "on" } else { "off" }),
+                    ["on"] => table.dry_run = true,
+                    ["off"] => table.dry_run = false,
+                    _ => report("ERROR: .dryrun [on|off].".into()),
+                },
                 ".constants" => {
                     println!("CONSTANT:");
                     println!("row size: {}", size_of::<Row>());
@@ -808,25 +5707,206 @@ fn main() {
                 }
                 ".tree" => {
                     println!("TREE:");
-                    table.pager.print_tree(0, 0);
+                    if let Err(e) =
+                        table
+                            .pager
+                            .print_tree(table.root_node_index, 0, &mut Visited::new())
+                    {
+                        report(e);
+                    }
+                }
+                ".optimize" => match table.optimize() {
+                    Ok((n_pages_before, n_pages)) => {
+                        println!("rebuilt the tree, {n_pages} pages down from {n_pages_before}.")
+                    }
+                    Err(e) => report(e),
+                },
+                ".analyze_tree" => match table.tree_levels() {
+                    Ok(levels) => print_tree_levels(&levels),
+                    Err(e) => report(e),
+                },
+                ".stats" => {
+                    let stats = &table.pager.stats;
+                    println!("STATS:");
+                    println!("cache hits: {}", stats.cache_hits);
+                    println!("cache misses: {}", stats.cache_misses);
+                    println!("pages read: {}", stats.pages_read);
+                    println!("read calls: {}", stats.read_calls);
+                    println!("pages written: {}", stats.pages_written);
+                    println!("write calls: {}", stats.write_calls);
                 }
-                _ => println!("ERROR: unknown command: '{input}'"),
+                ".dbinfo" => match table
+                    .stats()
+                    .and_then(|stats| Ok((stats, table.count()?, table.sequences()?)))
+                {
+                    Ok((stats, n_rows, sequences)) => {
+                        println!("DBINFO:");
+                        println!("pages: {}", table.pager.n_pages);
+                        println!("rows: {n_rows}");
+                        if stats.n_leaves == 0 {
+                            println!("stats: none, run analyze.");
+                        } else {
+                            println!("leaves: {}", stats.n_leaves);
+                            println!("leaf fill: {}%", stats.leaf_fill_percent);
+                            let histogram = stats.histogram.map(|key| key.to_string()).join(" ");
+                            println!("key histogram: {histogram}");
+                        }
+                        for (name, next) in sequences {
+                            println!("sequence {name}: next {next}");
+                        }
+                    }
+                    Err(e) => report(e),
+                },
+                ".recover" => match tokens[1..] {
+                    [new_path] => match table
+                        .flush()
+                        .and_then(|()| recover(&table.pager.file, new_path))
+                    {
+                        Ok(n_rows) => println!("recovered {n_rows} rows into {new_path}."),
+                        Err(e) => report(e),
+                    },
+                    _ => report(ERR_RECOVER_SYNTAX.into()),
+                },
+                ".snapshot" => match tokens[1..] {
+                    [path] => match table
+                        .flush()
+                        .and_then(|()| snapshot(&table.pager.file, table.pager.n_pages, path))
+                    {
+                        Ok(n_pages) => println!("snapshot of {n_pages} pages written to {path}."),
+                        Err(e) => report(e),
+                    },
+                    _ => report(ERR_SNAPSHOT_SYNTAX.into()),
+                },
+                ".export" => match table.export(&tokens[1..]) {
+                    Ok(n_rows) => println!("exported {n_rows} rows."),
+                    Err(e) => report(e),
+                },
+                ".import" => match table.import(&tokens[1..]).map_err(|error| {
+                    point_at_syntax_error(input, tokens.get(1).unwrap_or(&input), error)
+                }) {
+                    Ok((n_rows, 0)) => println!("imported {n_rows} rows."),
+                    Ok((n_rows, n_skipped)) => {
+                        println!("imported {n_rows} rows, skipped {n_skipped} lines.")
+                    }
+                    Err(e) => report(e),
+                },
+                _ => report(format!("ERROR: unknown command: '{input}'").into()),
             }
         } else {
             // exec statement
-            let tokens = input.split([' ', '\t']).collect::<Vec<_>>();
             match tokens[0] {
+                "insert" if table.dry_run => match table.dry_run_insert(&tokens[1..]) {
+                    Ok(outcome) => println!("dry run: {outcome}."),
+                    Err(e) => report(e),
+                },
+                "insert" if table.batch.is_some() => match table.parse_row(&tokens[1..], true) {
+                    Ok(row) => {
+                        table.batch.get_or_insert_default().rows.push(row);
+                        println!("queued.");
+                    }
+                    Err(e) => report(e),
+                },
                 "insert" => match table.insert(&tokens[1..]) {
                     Ok(()) => println!("executed."),
-                    Err(e) => println!("{e}"),
+                    Err(e) => report(e),
                 },
                 "select" => {
-                    table.select();
-                    println!("executed.");
+                    let text = text_after(input, tokens[0]);
+                    match Query::parse(text)
+                        .map_err(|error| point_at_syntax_error(input, text, error))
+                        .and_then(|query| table.select(&query))
+                    {
+                        Ok(()) => println!("executed."),
+                        Err(e) => report(e),
+                    }
+                }
+                // a bare explain analyze still explains the analyze statement
+                "explain" if tokens.len() > 2 && tokens[1] == "analyze" => {
+                    match explain_analyze(table, input, &tokens[2..]) {
+                        Ok((plan, analysis)) => {
+                            println!("QUERY PLAN:");
+                            for step in plan {
+                                println!("{step}");
+                            }
+                            println!("ANALYZE:");
+                            for line in analysis {
+                                println!("{line}");
+                            }
+                        }
+                        Err(e) => report(e),
+                    }
                 }
-                _ => println!("ERROR: unkown statement keyword: '{input}'"),
+                "explain" => match table.explain(&tokens[1..]) {
+                    Ok(plan) => {
+                        println!("QUERY PLAN:");
+                        for step in plan {
+                            println!("{step}");
+                        }
+                    }
+                    Err(e) => report(e),
+                },
+                "pragma" => match table.pragma(text_after(input, tokens[0])).map_err(|error| {
+                    point_at_syntax_error(input, text_after(input, tokens[0]), error)
+                }) {
+                    Ok(value) => {
+                        if let Some(value) = value {
+                            println!("[{value}]");
+                        }
+                        println!("executed.");
+                    }
+                    Err(e) => report(e),
+                },
+                "exists" => match tokens[1..] {
+                    [id] => match id.parse() {
+                        Ok(id) => match table.contains_key(id) {
+                            Ok(found) => {
+                                println!("[{}]", found as i64);
+                                println!("executed.");
+                            }
+                            Err(e) => report(e),
+                        },
+                        Err(_) => report(ERR_EXISTS_SYNTAX.into()),
+                    },
+                    _ => report(ERR_EXISTS_SYNTAX.into()),
+                },
+                "analyze" => match table.analyze() {
+                    Ok(_) => println!("executed."),
+                    Err(e) => report(e),
+                },
+                "create" => match table.create_sequence(&tokens[1..]) {
+                    Ok(()) => println!("executed."),
+                    Err(e) => report(e),
+                },
+                "truncate" => match table.truncate() {
+                    Ok(_) => println!("executed."),
+                    Err(e) => report(e),
+                },
+                _ => report(format!("ERROR: unkown statement keyword: '{input}'").into()),
+            }
+        }
+        let elapsed = table.statement_start.elapsed();
+        if table.slow.is_some_and(|slow| elapsed >= slow) {
+            eprintln!("SLOW {} ms: {input}", elapsed.as_millis());
+            if let Some(plan) = plan {
+                eprintln!("  plan: {}", plan.join(", "));
             }
+            eprintln!(
+                "  rows examined: {}, pages read: {}, cache hits: {}",
+                table.rows_examined,
+                table.pager.stats.pages_read - stats_before.0,
+                table.pager.stats.cache_hits - stats_before.1
+            );
+        }
+        if tokens[0] != ".history" {
+            History::record(&mut history, line, n_errors.get() == errors_before);
         }
         buf.clear();
     }
+    for table in attached.into_values().chain([main_table]) {
+        if let Err(error) = table.close() {
+            eprintln!("ERROR: db close: {error}");
+            status = status.max(exit_code(&*error));
+        }
+    }
+    process::exit(status);
 }
@@ -1,6 +1,8 @@
 PROG="rqlite"
 DB="test.db"
 PROMPT="rqlite>"
+# keep the repl history of the tests out of ~/.rqlite_history
+export RQLITE_HISTORY="test-history"
 NEW_LINE="
 "
 SUCCESS_TEST_COUNT=0
@@ -14,14 +16,14 @@ DESCRIPTION_MAX_SIZE=256
 ROW_SIZE=$((ID_SIZE + NAME_MAX_SIZE + DESCRIPTION_MAX_SIZE))
 NODE_KIND_SIZE=1
 NODE_IS_ROOT_SIZE=1
-NODE_PARENT_SIZE=4
 NODE_N_CELLS_SIZE=4
-NODE_HEADER_SIZE=$((NODE_KIND_SIZE + NODE_IS_ROOT_SIZE + NODE_PARENT_SIZE + NODE_N_CELLS_SIZE))
+NODE_HEADER_SIZE=$((NODE_KIND_SIZE + NODE_IS_ROOT_SIZE + NODE_N_CELLS_SIZE))
 LEAF_NODE_NEXT_CELL_SIZE=4
 LEAF_NODE_HEADER_SIZE=$((NODE_HEADER_SIZE + LEAF_NODE_NEXT_CELL_SIZE))
 LEAF_NODE_CELL_SIZE=$((ROW_SIZE + ID_SIZE))
 LEAF_NODE_SPACE_FOR_CELLS=$((PAGE_SIZE - LEAF_NODE_HEADER_SIZE))
-LEAF_NODE_CELL_MAX_NUM=$((LEAF_NODE_SPACE_FOR_CELLS / LEAF_NODE_CELL_SIZE))
+LEAF_NODE_SLOT_SIZE=2
+LEAF_NODE_CELL_MAX_NUM=$((LEAF_NODE_SPACE_FOR_CELLS / (LEAF_NODE_SLOT_SIZE + LEAF_NODE_CELL_SIZE)))
 SPLIT_RIGHT_LEAF_NODE_NUM=$(((LEAF_NODE_CELL_MAX_NUM + 1) / 2))
 SPLIT_LEFT_LEAF_NODE_NUM=$(((LEAF_NODE_CELL_MAX_NUM + 1) - SPLIT_RIGHT_LEAF_NODE_NUM))
 
@@ -67,7 +69,7 @@ function assert_and_drop_db() {
     echo "===========   got*    ==========="
   fi
   # drop db
-  rm "$DB" > /dev/null 2>&1
+  rm "$DB" "$RQLITE_HISTORY" > /dev/null 2>&1
 }
 
 function test_insert_one() {
@@ -121,9 +123,28 @@ $PROMPT "
   assert_and_drop_db "$got" "$expected" "insert_duplicated_id"
 }
 
+function test_insert_duplicated_id_after_split() {
+  local commands=()
+  local expected=""
+  for i in $(seq 1 $((LEAF_NODE_CELL_MAX_NUM * 2))); do
+    commands+=("insert $i foo$i bar$i")
+    expected+="$PROMPT executed.$NEW_LINE"
+  done
+  # the root is internal now, the duplicate sits deep in the left leaf
+  commands+=("insert 5 foo bar" "select id where id = 5" ".exit")
+  local got=$(exec_command "${commands[@]}")
+  expected+="$PROMPT ERROR: key '5' already exist.
+$PROMPT [5]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "insert_duplicated_id_after_split"
+}
+
 function test_insert_pass_max() {
   local commands=()
-  for i in $(seq 1 $((2*SPLIT_LEFT_LEAF_NODE_NUM + SPLIT_RIGHT_LEAF_NODE_NUM))); do
+  # page 0 is the header and the root stays internal, every other page is a
+  # leaf packed full by sequential inserts
+  for i in $(seq 1 $(((PAGE_MAX_NUMS - 2) * LEAF_NODE_CELL_MAX_NUM + 1))); do
     commands+=("insert $i name$i description$i")
   done
   commands+=(".exit")
@@ -134,10 +155,61 @@ function test_insert_pass_max() {
   local result_arr=($result)
   IFS="$save_IFS"
   got="${result_arr[${#result_arr[@]}-2]}" # get second to the last item
-  expected="TODO: update parent after split"
+  expected="$PROMPT ERROR: table reach max size."
   assert_and_drop_db "$got" "$expected" "insert_pass_max"
 }
 
+function test_insert_split_non_root_leaf() {
+  local commands=()
+  for i in $(seq 1 $((2 * LEAF_NODE_CELL_MAX_NUM))); do
+    commands+=("insert $((i * 2)) name$i description$i")
+  done
+  commands+=("insert 3 name3 description3")
+  commands+=(".tree")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}")
+  local expected=""
+  for i in $(seq 1 $((2 * LEAF_NODE_CELL_MAX_NUM + 1))); do
+    expected+="$PROMPT executed.$NEW_LINE"
+  done
+  expected+="$PROMPT TREE:
+- internal (size 2)
+  - leaf (size 7)
+    - 2
+    - 3
+    - 4
+    - 6
+    - 8
+    - 10
+    - 12
+  - key 12
+  - leaf (size 7)
+    - 14
+    - 16
+    - 18
+    - 20
+    - 22
+    - 24
+    - 26
+  - key 26
+  - leaf (size 13)
+    - 28
+    - 30
+    - 32
+    - 34
+    - 36
+    - 38
+    - 40
+    - 42
+    - 44
+    - 46
+    - 48
+    - 50
+    - 52
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "insert_split_non_root_leaf"
+}
+
 function test_insert_out_of_order() {
   local commands=(
     "insert 100 foo100 bar100"
@@ -197,6 +269,26 @@ $PROMPT "
   assert_and_drop_db "$got" "$expected" "name_and_description_max_len"
 }
 
+function test_multi_byte_name() {
+  local name=""
+  for _ in $(seq 1 $((NAME_MAX_SIZE / 2))); do
+    name+="é"
+  done
+  local commands=(
+    "insert 1 $name foo"
+    "insert 2 ${name}a foo"
+    "select"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  local expected="$PROMPT executed.
+$PROMPT ERROR: name is $((NAME_MAX_SIZE + 1)) bytes ($((NAME_MAX_SIZE / 2 + 1)) characters), max $NAME_MAX_SIZE: '${name}a'.
+$PROMPT [1, $name, foo]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "multi_byte_name"
+}
+
 function test_name_len_pass_max() {
   local name=""
   for _ in $(seq 1 $(($NAME_MAX_SIZE + 1))); do
@@ -207,7 +299,7 @@ function test_name_len_pass_max() {
     ".exit"
 )
   local got=$(exec_command "${commands[@]}")
-  local expected="$PROMPT ERROR: name too long.
+  local expected="$PROMPT ERROR: name is $((NAME_MAX_SIZE + 1)) bytes, max $NAME_MAX_SIZE: '$name'.
 $PROMPT "
   assert_and_drop_db "$got" "$expected" "name_len_pass_max"
 }
@@ -222,11 +314,94 @@ function test_description_pass_max() {
     ".exit"
 )
   local got=$(exec_command "${commands[@]}")
-  local expected="$PROMPT ERROR: description too long.
+  local expected="$PROMPT ERROR: description is $((DESCRIPTION_MAX_SIZE + 1)) bytes, max $DESCRIPTION_MAX_SIZE: '$description'.
 $PROMPT "
   assert_and_drop_db "$got" "$expected" "description_pass_max"
 }
 
+function test_import_sorted() {
+  local csv="test.csv"
+  for i in $(seq 1 $((LEAF_NODE_CELL_MAX_NUM + 2))); do
+    echo "$i,name$i,description$i"
+  done > "$csv"
+  local commands=(
+    ".import --sorted $csv"
+    ".tree"
+    ".import --sorted $csv"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  rm "$csv"
+  local expected="$PROMPT imported $((LEAF_NODE_CELL_MAX_NUM + 2)) rows.
+$PROMPT TREE:
+- internal (size 1)
+  - leaf (size 13)
+    - 1
+    - 2
+    - 3
+    - 4
+    - 5
+    - 6
+    - 7
+    - 8
+    - 9
+    - 10
+    - 11
+    - 12
+    - 13
+  - key 13
+  - leaf (size 2)
+    - 14
+    - 15
+$PROMPT ERROR: bulk load needs an empty table.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "import_sorted"
+}
+
+function test_import_unsorted() {
+  local csv="test.csv"
+  printf "%s\n" "3,foo3,bar3" "1,foo1,bar1" "2,foo2,bar2" > "$csv"
+  local commands=(
+    ".import $csv"
+    "select"
+    ".import $csv"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  rm "$csv"
+  local expected="$PROMPT imported 3 rows.
+$PROMPT [1, foo1, bar1]
+[2, foo2, bar2]
+[3, foo3, bar3]
+executed.
+$PROMPT ERROR: key '1' already exist.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "import_unsorted"
+}
+
+function test_import_csv_options() {
+  local csv="test.csv"
+  local log="import.log"
+  printf "%s\n" "Name;ID;Extra;Description" "foo;1;x;bar" "baz;two;x;qux" "NULL;3;x;NULL" > "$csv"
+  local commands=(
+    ".import --header --delimiter ; $csv"
+    ".import --header --delimiter ; --null NULL --on-error log=$log $csv"
+    "select"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  got+="$NEW_LINE$(cat "$log")"
+  rm "$csv" "$log"
+  local expected="$PROMPT ERROR: line 3: id 'two' is not an integer.
+$PROMPT imported 2 rows, skipped 1 lines.
+$PROMPT [1, foo, bar]
+[3, , ]
+executed.
+$PROMPT 
+ERROR: line 3: id 'two' is not an integer."
+  assert_and_drop_db "$got" "$expected" "import_csv_options"
+}
+
 function test_persistence() {
   local commands1=(
     "insert 1 foo bar"
@@ -244,6 +419,1240 @@ $PROMPT "
   assert_and_drop_db "$got" "$expected" "persistence"
 }
 
+function test_persistence_across_sessions() {
+  local commands1=(
+    "insert 1 foo bar"
+    ".exit"
+  )
+  local commands2=(
+    "insert 2 baz qux"
+    ".exit"
+  )
+  local commands3=(
+    "select"
+    ".exit"
+  )
+  exec_command "${commands1[@]}" > /dev/null # for side effect
+  exec_command "${commands2[@]}" > /dev/null # for side effect
+  local got=$(exec_command "${commands3[@]}")
+  local expected="$PROMPT [1, foo, bar]
+[2, baz, qux]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "persistence_across_sessions"
+}
+
+function test_stats() {
+  local commands1=(
+    "insert 1 foo bar"
+    ".exit"
+  )
+  local commands2=(
+    "select"
+    ".stats"
+    ".exit"
+  )
+  exec_command "${commands1[@]}" > /dev/null # for side effect
+  local got=$(exec_command "${commands2[@]}")
+  local expected="$PROMPT [1, foo, bar]
+executed.
+$PROMPT STATS:
+cache hits: 1
+cache misses: 1
+pages read: 1
+read calls: 1
+pages written: 0
+write calls: 0
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "stats"
+}
+
+# a scan that crosses into a leaf reads the leaves after it ahead in one
+# call, so a cold scan of several leaves takes fewer read calls than pages
+function test_stats_readahead() {
+  local commands1=()
+  for i in $(seq 1 $((8 * LEAF_NODE_CELL_MAX_NUM))); do
+    commands1+=("insert $i foo bar")
+  done
+  commands1+=(".exit")
+  local commands2=(
+    "select id where id < 0"
+    ".stats"
+    ".exit"
+  )
+  exec_command "${commands1[@]}" > /dev/null # for side effect
+  local stats=$(exec_command "${commands2[@]}")
+  local pages_read=$(echo "$stats" | grep "^pages read:" | cut -d " " -f 3)
+  local read_calls=$(echo "$stats" | grep "^read calls:" | cut -d " " -f 3)
+  local got="pages read: $pages_read
+read calls: $read_calls
+fewer calls than pages: $((read_calls < pages_read))"
+  local expected="pages read: 9
+read calls: 5
+fewer calls than pages: 1"
+  assert_and_drop_db "$got" "$expected" "stats_readahead"
+}
+
+function test_analyze() {
+  local commands=(
+    ".dbinfo"
+  )
+  for i in $(seq 1 20); do
+    commands+=("insert $i name$i description$i")
+  done
+  commands+=(
+    "analyze"
+    ".dbinfo"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}" | grep -v "executed.")
+  local expected="$PROMPT DBINFO:
+pages: 2
+rows: 0
+stats: none, run analyze.
+$PROMPT DBINFO:
+pages: 4
+rows: 20
+leaves: 2
+leaf fill: 76%
+key histogram: 1 2 4 6 8 10 12 14 16 18 20
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "analyze"
+}
+
+function test_explain() {
+  local commands=()
+  for i in $(seq 1 $((LEAF_NODE_CELL_MAX_NUM + 1))); do
+    commands+=("insert $((i * 2)) name$i description$i")
+  done
+  commands+=(
+    "explain insert 100 foo bar"
+    "explain insert 3 foo bar"
+    "explain select"
+    "analyze"
+    "explain select"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}" | grep -v "executed.")
+  local expected="$PROMPT QUERY PLAN:
+append to rightmost leaf page 2
+pages expected: 1
+$PROMPT QUERY PLAN:
+primary key seek to leaf page 3
+split leaf page 3
+pages expected: 2
+$PROMPT QUERY PLAN:
+full scan of primary key from leaf page 3
+pages expected: unknown, run analyze.
+$PROMPT QUERY PLAN:
+full scan of primary key from leaf page 3
+pages expected: 3
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "explain"
+}
+
+function test_corrupt_node_kind() {
+  local commands1=(
+    "insert 1 foo bar"
+    ".exit"
+  )
+  local commands2=(
+    "select"
+    ".exit"
+  )
+  exec_command "${commands1[@]}" > /dev/null # for side effect
+  # overwrite the kind byte of the root, page 1
+  printf '\x09' | dd of="$DB" bs=1 seek=$PAGE_SIZE conv=notrunc 2> /dev/null
+  local got=$(exec_command "${commands2[@]}")
+  local expected="$PROMPT ERROR: database corrupt, page 1 kind: unknown node kind 9.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "corrupt_node_kind"
+}
+
+function test_corrupt_leaf_cycle() {
+  local commands1=()
+  for i in $(seq 1 $((LEAF_NODE_CELL_MAX_NUM + 1))); do
+    commands1+=("insert $i user$i person$i@example.com")
+  done
+  commands1+=(".exit")
+  local commands2=(
+    "select id where id > 12"
+    ".exit"
+  )
+  exec_command "${commands1[@]}" > /dev/null # for side effect
+  # the split leaves page 3 holding the low keys and linked to page 2; link
+  # page 2 back to page 3
+  printf '\x03\x00\x00\x00' | dd of="$DB" bs=1 seek=$((2 * PAGE_SIZE + NODE_HEADER_SIZE)) conv=notrunc 2> /dev/null
+  local got=$(exec_command "${commands2[@]}")
+  got+="$NEW_LINE$("./$PROG" dump "$DB" 2>&1 > /dev/null; echo "exit code $?")"
+  local expected="$PROMPT [13]
+[14]
+ERROR: database corrupt, page 3 next_leaf: reached twice, the leaf chain has a cycle.
+$PROMPT 
+ERROR: database corrupt, page 3 next_leaf: reached twice, the leaf chain has a cycle.
+exit code 3"
+  assert_and_drop_db "$got" "$expected" "corrupt_leaf_cycle"
+}
+
+function test_not_a_database() {
+  head -c $((2 * PAGE_SIZE)) /dev/zero > "$DB"
+  local got=$(exec_command ".exit")
+  local expected="ERROR: init pager: ERROR: database corrupt, page 0 magic: not an rqlite database."
+  assert_and_drop_db "$got" "$expected" "not_a_database"
+}
+
+function test_disk_full() {
+  # writes to /dev/full always fail with ENOSPC
+  local got=$(printf "%s\n" "insert 1 foo bar" ".exit" | "./$PROG" /dev/full 2>&1)
+  local expected="$PROMPT executed.
+$PROMPT ERROR: disk full, 2 pages unflushed.
+$PROMPT ERROR: db close: ERROR: disk full, 2 pages unflushed."
+  assert_and_drop_db "$got" "$expected" "disk_full"
+}
+
+# a failed flush on .exit keeps the repl open, the flush on close at end of
+# input fails again and that error sets the exit status
+function test_close_flush_error() {
+  local stderr="stderr.txt"
+  local got=$(printf "%s\n" "insert 1 foo bar" ".exit" "select" | "./$PROG" /dev/full 2> "$stderr"; echo "exit $?")
+  got+="$NEW_LINE$(cat "$stderr")"
+  rm "$stderr"
+  local expected="executed.
+[1, foo, bar]
+executed.
+exit 2
+$PROMPT $PROMPT ERROR: disk full, 2 pages unflushed.
+$PROMPT $PROMPT ERROR: db close: ERROR: disk full, 2 pages unflushed."
+  assert_and_drop_db "$got" "$expected" "close_flush_error"
+}
+
+# a format 3 file, whose node headers still had the parent field, holding
+# rows 1 to 14 over two leaves
+function test_migrate() {
+  local old="fixtures/format-3.db"
+  local commands=(
+    "select where id = 1"
+    "select count(*)"
+    ".exit"
+  )
+  local got=$(printf "%s\n" "${commands[@]}" | "./$PROG" "$old" 2>&1)
+  got+="$NEW_LINE$("./$PROG" migrate "$old" "$DB")"
+  got+="$NEW_LINE$(exec_command "${commands[@]}" | sed 's/ \{2,\}/ /g')"
+  local expected="ERROR: init pager: ERROR: database format version 3 is older than 4, upgrade it with rqlite migrate.
+migrated $old from format version 3 to 4.
+$PROMPT [1, name1, desc1]
+executed.
+$PROMPT [14]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "migrate"
+}
+
+# headerless files as the baseline build (cells packed after the leaf header)
+# and the build after slot arrays (before the header page) wrote them, each
+# holding rows 1 to 14 over two leaves
+function test_migrate_version_0() {
+  local commands=(
+    "select count(*)"
+    "select where id > 12"
+    ".exit"
+  )
+  local got=""
+  for old in fixtures/baseline.db fixtures/slotted-headerless.db; do
+    got+="$("./$PROG" migrate "$old" "$DB")$NEW_LINE"
+    got+="$(exec_command "${commands[@]}" | sed 's/ \{2,\}/ /g')$NEW_LINE"
+    rm "$DB"
+  done
+  local expected="migrated fixtures/baseline.db from format version 0 to 4.
+$PROMPT [14]
+executed.
+$PROMPT [13, name13, desc13]
+[14, name14, desc14]
+executed.
+$PROMPT 
+migrated fixtures/slotted-headerless.db from format version 0 to 4.
+$PROMPT [14]
+executed.
+$PROMPT [13, name13, desc13]
+[14, name14, desc14]
+executed.
+$PROMPT 
+"
+  assert_and_drop_db "$got" "$expected" "migrate version 0"
+}
+
+function test_trailing_tokens() {
+  local commands=(
+    "analyze extra tokens"
+    "insert 1 foo bar baz"
+    "select name frm"
+    "select id where id = 'x"
+    "pragma timeout = 5 now"
+    ".profile main now"
+    ".exit now"
+  )
+  local got=$(exec_command "${commands[@]}")
+  local expected="$PROMPT ERROR: unexpected 'extra' after analyze.
+analyze extra tokens
+        ^
+$PROMPT ERROR: unexpected 'baz' after insert.
+insert 1 foo bar baz
+                 ^
+$PROMPT ERROR: unexpected 'frm' in select.
+select name frm
+            ^
+$PROMPT ERROR: unterminated string.
+select id where id = 'x
+                     ^
+$PROMPT ERROR: unexpected 'now' after pragma.
+pragma timeout = 5 now
+                   ^
+$PROMPT ERROR: unexpected 'now' after .profile.
+.profile main now
+              ^
+$PROMPT ERROR: unexpected 'now' after .exit.
+.exit now
+      ^
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "trailing_tokens"
+}
+
+function test_stdout_and_exit_codes() {
+  local got=$(printf "%s\n" "insert 1 foo bar" "select" | "./$PROG" "$DB" 2> /dev/null; echo "exit $?")
+  got+="$NEW_LINE$(printf "%s\n" "insert 1 foo bar" | "./$PROG" "$DB" > /dev/null 2>&1; echo "exit $?")"
+  got+="$NEW_LINE$("./$PROG" > /dev/null 2>&1; echo "exit $?")"
+  printf '\x09' | dd of="$DB" bs=1 seek=$PAGE_SIZE conv=notrunc 2> /dev/null
+  got+="$NEW_LINE$(printf "%s\n" "select" | "./$PROG" "$DB" > /dev/null 2>&1; echo "exit $?")"
+  local expected="executed.
+[1, foo, bar]
+executed.
+exit 0
+exit 2
+exit 1
+exit 3"
+  assert_and_drop_db "$got" "$expected" "stdout_and_exit_codes"
+}
+
+function test_recover() {
+  local recovered="recovered.db"
+  local commands1=()
+  for i in $(seq 1 $((LEAF_NODE_CELL_MAX_NUM + 1))); do
+    commands1+=("insert $i name$i description$i")
+  done
+  commands1+=(".exit")
+  local commands2=(
+    "select"
+    ".recover $recovered"
+    ".exit"
+  )
+  exec_command "${commands1[@]}" > /dev/null # for side effect
+  # break the internal root, the leaves under it are still intact
+  printf '\x09' | dd of="$DB" bs=1 seek=$PAGE_SIZE conv=notrunc 2> /dev/null
+  local got=$(exec_command "${commands2[@]}")
+  got+="$NEW_LINE$(printf "%s\n" "select" | "./$PROG" "$recovered" 2> /dev/null)"
+  rm "$recovered"
+  local expected="$PROMPT ERROR: database corrupt, page 1 kind: unknown node kind 9.
+$PROMPT recovered $((LEAF_NODE_CELL_MAX_NUM + 1)) rows into $recovered.
+$PROMPT $NEW_LINE"
+  for i in $(seq 1 $((LEAF_NODE_CELL_MAX_NUM + 1))); do
+    expected+="[$i, name$i, description$i]$NEW_LINE"
+  done
+  expected+="executed."
+  assert_and_drop_db "$got" "$expected" "recover"
+}
+
+function test_export_sqlite() {
+  local exported="exported.sqlite"
+  local commands=(
+    "insert 1 foo bar"
+    ".export sqlite $exported"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  # schema on page 1, the single leaf of the table on page 2
+  got+="$NEW_LINE$(head -c 15 "$exported") $(wc -c < "$exported")"
+  rm "$exported"
+  local expected="$PROMPT executed.
+$PROMPT exported 1 rows.
+$PROMPT 
+SQLite format 3 $((2 * 4096))"
+  assert_and_drop_db "$got" "$expected" "export_sqlite"
+}
+
+function test_import_sqlite() {
+  local exported="exported.sqlite"
+  local commands=(
+    "insert 2 foo bar"
+    "insert 1 baz qux"
+    ".export sqlite $exported"
+    ".exit"
+  )
+  exec_command "${commands[@]}" > /dev/null
+  rm "$DB"
+  commands=(
+    ".import sqlite $exported"
+    "select"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  rm "$exported"
+  local expected="$PROMPT imported 2 rows.
+$PROMPT [1, baz, qux]
+[2, foo, bar]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "import_sqlite"
+}
+
+function test_jsonl() {
+  local exported="exported.jsonl"
+  local commands=(
+    "insert 1 foo bar"
+    "insert 2 \"quoted\" é"
+    ".export jsonl $exported"
+    ".exit"
+  )
+  exec_command "${commands[@]}" > /dev/null
+  local got=$(cat "$exported")
+  rm "$DB"
+  commands=(
+    ".import jsonl $exported"
+    "select"
+    ".exit"
+  )
+  got+="$NEW_LINE$(exec_command "${commands[@]}")"
+  rm "$exported"
+  local expected='{"id":1,"name":"foo","description":"bar"}
+{"id":2,"name":"\"quoted\"","description":"é"}'
+  expected+="$NEW_LINE$PROMPT imported 2 rows.
+$PROMPT [1, foo, bar]
+[2, \"quoted\", é]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "jsonl"
+}
+
+function test_diff() {
+  local other="other.db"
+  local commands1=(
+    "insert 1 foo bar"
+    "insert 2 baz qux"
+    ".exit"
+  )
+  local commands2=(
+    "insert 2 baz quux"
+    "insert 3 new row"
+    ".exit"
+  )
+  exec_command "${commands1[@]}" > /dev/null # for side effect
+  mv "$DB" "$other"
+  exec_command "${commands2[@]}" > /dev/null # for side effect
+  local got=$("./$PROG" diff --structure "$other" "$DB")
+  rm "$other"
+  local expected="pages: 2 vs 2
+depth: 1 vs 1
+- [1, foo, bar]
+- [2, baz, qux]
++ [2, baz, quux]
++ [3, new, row]
+1 deleted, 1 inserted, 1 changed."
+  assert_and_drop_db "$got" "$expected" "diff"
+}
+
+function test_dump_restore() {
+  local restored="restored.db"
+  local commands=(
+    "insert 1 foo bar"
+    "insert 2 baz qux"
+    ".exit"
+  )
+  exec_command "${commands[@]}" > /dev/null # for side effect
+  local got=$("./$PROG" dump "$DB" | "./$PROG" restore "$restored")
+  got+="$NEW_LINE$(printf "select\n.exit\n" | "./$PROG" "$restored" 2>&1)"
+  # a damaged stream is refused before anything is written
+  got+="$NEW_LINE$("./$PROG" dump "$DB" | head -c 30 | "./$PROG" restore "$restored" 2>&1)"
+  rm "$restored"
+  local expected="restored 2 rows into $restored.
+$PROMPT [1, foo, bar]
+[2, baz, qux]
+executed.
+$PROMPT 
+ERROR: dump stream is truncated."
+  assert_and_drop_db "$got" "$expected" "dump_restore"
+}
+
+function test_snapshot_verify() {
+  local snapshot="snapshot.bin"
+  local commands=(
+    "insert 1 foo bar"
+    ".snapshot $snapshot"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  got+="$NEW_LINE$("./$PROG" verify "$snapshot")"
+  # flip a byte in the last page
+  printf '\xff' | dd of="$snapshot" bs=1 seek=$(($(wc -c < "$snapshot") - 1)) conv=notrunc 2> /dev/null
+  got+="$NEW_LINE$("./$PROG" verify "$snapshot" 2>&1)"
+  rm "$snapshot"
+  local expected="$PROMPT executed.
+$PROMPT snapshot of 2 pages written to $snapshot.
+$PROMPT 
+$snapshot: ok, 2 pages, format version 4.
+ERROR: snapshot page 1 checksum mismatch."
+  assert_and_drop_db "$got" "$expected" "snapshot_verify"
+}
+
+function test_stats_subcommand() {
+  local commands=(
+    "insert 1 foo bar"
+    ".exit"
+  )
+  exec_command "${commands[@]}" > /dev/null # for side effect
+  local got=$("./$PROG" stats "$DB")
+  got+="$NEW_LINE$("./$PROG" stats --json "$DB")"
+  local free=$((PAGE_SIZE - LEAF_NODE_HEADER_SIZE - 2 - LEAF_NODE_CELL_SIZE))
+  local padding=$((32 - 3 + 256 - 3))
+  local expected="pages: 2
+internal pages: 0
+leaf pages: 1
+unreachable pages: 0
+depth: 1
+rows: 1
+free bytes: $free
+padding bytes: $padding
+leaf fill histogram (10% buckets): 1 0 0 0 0 0 0 0 0 0
+{\"pages\":2,\"internal_pages\":0,\"leaf_pages\":1,\"unreachable_pages\":0,\"depth\":1,\"rows\":1,\"free_bytes\":$free,\"padding_bytes\":$padding,\"leaf_fill_histogram\":[1,0,0,0,0,0,0,0,0,0]}"
+  assert_and_drop_db "$got" "$expected" "stats_subcommand"
+}
+
+function test_select_expressions() {
+  local commands=(
+    "insert 1 Foo bar"
+    "insert 2 baz quux"
+    "select id, upper(name), length(description), substr(name, 2, 1)"
+    "select name where id >= 2 and description != 'bar'"
+    "select 'it''s' where name = 'Foo'"
+    "select nope"
+    "select id where descrption = 'bar'"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  local expected="$PROMPT executed.
+$PROMPT executed.
+$PROMPT [1, FOO, 3, o]
+[2, BAZ, 4, a]
+executed.
+$PROMPT [baz]
+executed.
+$PROMPT [it's]
+executed.
+$PROMPT ERROR: no column 'nope'; the columns are id, name, description.
+select nope
+       ^
+$PROMPT ERROR: no column 'descrption'; did you mean 'description'?
+select id where descrption = 'bar'
+                ^
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "select_expressions"
+}
+
+function test_last_insert_rowid() {
+  local csv="test.csv"
+  printf "%s\n" "5,foo5,bar5" "9,foo9,bar9" > "$csv"
+  local commands=(
+    "insert 3 foo bar"
+    "select last_insert_rowid(), changes(), total_changes()"
+    ".import $csv"
+    "select last_insert_rowid(), changes(), total_changes()"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  rm "$csv"
+  local expected="$PROMPT executed.
+$PROMPT [3, 1, 1]
+executed.
+$PROMPT imported 2 rows.
+$PROMPT [9, 2, 3]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "last_insert_rowid"
+}
+
+function test_timeout() {
+  local commands=(
+    ".timeout"
+    ".timeout 1500"
+    ".timeout"
+    ".timeout 0"
+    ".timeout"
+    ".exit"
+  )
+  local got=$(printf "%s\n" "${commands[@]}" | "./$PROG" --timeout 250 "$DB" 2>&1)
+  got+="$NEW_LINE$("./$PROG" --timeout soon "$DB" 2>&1)"
+  local expected="$PROMPT timeout: 250 ms.
+$PROMPT $PROMPT timeout: 1500 ms.
+$PROMPT $PROMPT timeout: off.
+$PROMPT 
+ERROR: 'soon' is not a number of milliseconds."
+  assert_and_drop_db "$got" "$expected" "timeout"
+}
+
+function test_slow_log() {
+  local commands=(
+    "insert 1 foo bar"
+    "select name where id = 1"
+    ".exit"
+  )
+  local got=$(printf "%s\n" "${commands[@]}" | "./$PROG" --slow-ms 0 "$DB" 2>&1 | sed "s/SLOW [0-9]* ms/SLOW n ms/")
+  local expected="$PROMPT executed.
+SLOW n ms: insert 1 foo bar
+  plan: primary key seek to leaf page 1, pages expected: 1
+  rows examined: 0, pages read: 0, cache hits: 4
+$PROMPT [foo]
+executed.
+SLOW n ms: select name where id = 1
+  plan: full scan of primary key from leaf page 1, filter rows by where clause, pages expected: unknown, run analyze.
+  rows examined: 1, pages read: 0, cache hits: 2
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "slow_log"
+}
+
+function test_pragma() {
+  local commands=(
+    "pragma page_size"
+    "pragma cache_size = 512"
+    "pragma synchronous=on"
+    "pragma synchronous"
+    "insert 1 foo bar"
+    ".flush"
+    "pragma timeout=300"
+    "pragma timeout"
+    "pragma foo"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  local expected="$PROMPT [$PAGE_SIZE]
+executed.
+$PROMPT ERROR: cache_size is fixed, the cache holds all $PAGE_MAX_NUMS pages a database can have.
+$PROMPT executed.
+$PROMPT [on]
+executed.
+$PROMPT executed.
+$PROMPT flushed.
+$PROMPT executed.
+$PROMPT [300]
+executed.
+$PROMPT ERROR: unknown pragma 'foo'.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "pragma"
+}
+
+function test_order_by() {
+  local csv="test.csv"
+  # more rows than one in-memory run, so the sort spills and merges
+  for i in $(seq 1 300); do
+    echo "$i,n$(( i * 7 % 10 )),d"
+  done > "$csv"
+  local commands=(
+    ".import --sorted $csv"
+    "select id, name order by name"
+    "select id where id > 297 order by id desc"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  local expected="$PROMPT imported 300 rows.
+$PROMPT $(sort -s -t, -k2,2 "$csv" | sed 's/^\([0-9]*\),\([^,]*\),.*/[\1, \2]/')
+executed.
+$PROMPT [300]
+[299]
+[298]
+executed.
+$PROMPT "
+  rm "$csv"
+  assert_and_drop_db "$got" "$expected" "order_by"
+}
+
+function test_split_sequential_edges() {
+  local commands=("pragma leaf_fill=90")
+  for i in $(seq $((LEAF_NODE_CELL_MAX_NUM + 1))); do
+    commands+=("insert $((100 + i)) up up")
+    commands+=("insert $((100 - i)) down down")
+  done
+  commands+=(".tree")
+  commands+=("pragma leaf_fill=101")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -v "^    - ")
+  local expected="$PROMPT executed."
+  for i in $(seq $((2 * LEAF_NODE_CELL_MAX_NUM + 2))); do
+    expected+="$NEW_LINE$PROMPT executed."
+  done
+  expected+="$NEW_LINE$PROMPT TREE:
+- internal (size 2)
+  - leaf (size 9)
+  - key 94
+  - leaf (size 12)
+  - key 107
+  - leaf (size 7)
+$PROMPT ERROR: leaf_fill is a percent from 50 to 100.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "split_sequential_edges"
+}
+
+function test_exists() {
+  local commands=()
+  for i in $(seq $((LEAF_NODE_CELL_MAX_NUM + 1))); do
+    commands+=("insert $((i * 2)) foo bar")
+  done
+  commands+=("exists 2")
+  commands+=("exists 3")
+  commands+=("exists $(((LEAF_NODE_CELL_MAX_NUM + 1) * 2))")
+  commands+=("exists x")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -v "executed.$")
+  local expected="$PROMPT [1]
+$PROMPT [0]
+$PROMPT [1]
+$PROMPT ERROR: exists <id>.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "exists"
+}
+
+function test_count() {
+  local commands=(
+    "select count(*)"
+  )
+  for i in $(seq $((LEAF_NODE_CELL_MAX_NUM + 1))); do
+    commands+=("insert $i foo$((i % 2)) bar")
+  done
+  commands+=("insert 1 dup bar")
+  commands+=("select count(*)")
+  commands+=("select count(*) where name = 'foo1'")
+  commands+=("explain select count(*)")
+  commands+=("select count(*) order by id")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -v "^$PROMPT executed.$")
+  local expected="$PROMPT [0]
+executed.
+$PROMPT ERROR: key '1' already exist.
+$PROMPT [$((LEAF_NODE_CELL_MAX_NUM + 1))]
+executed.
+$PROMPT [$(((LEAF_NODE_CELL_MAX_NUM + 2) / 2))]
+executed.
+$PROMPT QUERY PLAN:
+row count from header page 0
+pages expected: 1
+$PROMPT ERROR: count(*) gives one row, there is nothing to order.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "count"
+}
+
+function test_sample() {
+  local commands=()
+  for i in $(seq $((2 * LEAF_NODE_CELL_MAX_NUM))); do
+    commands+=("insert $i foo bar")
+  done
+  commands+=("select sample 3 name")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -c "\[foo\]$")
+  rm "$DB"
+  commands=(
+    "select sample 2"
+    "insert 7 foo bar"
+    "select sample 2"
+    "select sample 2 where id > 1"
+    "select sample x"
+    "explain select sample 2"
+    ".exit"
+  )
+  got+="$NEW_LINE$(exec_command "${commands[@]}")"
+  local expected="3
+$PROMPT executed.
+$PROMPT executed.
+$PROMPT [7, foo, bar]
+executed.
+$PROMPT ERROR: sample takes no where or order by.
+$PROMPT ERROR: sample needs a number of rows.
+$PROMPT QUERY PLAN:
+random descent from the root for each of 2 rows
+pages expected: about 2
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "sample"
+}
+
+function test_first_last() {
+  local commands=(
+    "select first"
+  )
+  for i in $(seq $((LEAF_NODE_CELL_MAX_NUM + 1)) -1 1); do
+    commands+=("insert $((i * 2)) name$i bar")
+  done
+  commands+=("select first")
+  commands+=("select last name")
+  commands+=("explain select last")
+  commands+=("select first where id > 2")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -v "^$PROMPT executed.$")
+  local expected="$PROMPT [2, name1, bar]
+executed.
+$PROMPT [name$((LEAF_NODE_CELL_MAX_NUM + 1))]
+executed.
+$PROMPT QUERY PLAN:
+descend to rightmost leaf page 2
+pages expected: 2
+$PROMPT ERROR: first takes no where or order by.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "first_last"
+}
+
+function test_after_limit() {
+  local commands=()
+  for i in $(seq $((2 * LEAF_NODE_CELL_MAX_NUM))); do
+    commands+=("insert $((i * 2)) foo bar")
+  done
+  commands+=("select after 10 limit 3 id")
+  commands+=("insert 13 new bar")
+  commands+=("select after 14 limit 2 id")
+  commands+=("select after $((4 * LEAF_NODE_CELL_MAX_NUM - 2)) id")
+  commands+=("select after $((4 * LEAF_NODE_CELL_MAX_NUM)) limit 5")
+  commands+=("select after 10 limit x")
+  commands+=("explain select after 10 limit 3")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -v "^$PROMPT executed.$")
+  local expected="$PROMPT [12]
+[14]
+[16]
+bookmark: bm0000000300080000000000000012
+executed.
+$PROMPT [16]
+[18]
+bookmark: bm0000000400030000000000000014
+executed.
+$PROMPT [$((4 * LEAF_NODE_CELL_MAX_NUM))]
+executed.
+$PROMPT ERROR: limit needs a number of rows.
+$PROMPT QUERY PLAN:
+primary key seek past 10 to leaf page 3
+scan along the leaves for at most 3 rows
+pages expected: about 3
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "after_limit"
+}
+
+function test_open_as() {
+  local other="other.db"
+  local commands=(
+    "insert 1 main bar"
+    ".open --as archive $other"
+    "archive insert 1 archived bar"
+    "archive   select name"
+    "select name"
+    ".open --as again $DB"
+    ".open --as select $other"
+    ".open $other"
+    "archive .exit"
+  )
+  local got=$(exec_command "${commands[@]}")
+  got+="$NEW_LINE$(printf "%s\n" ".open --as archive $other" "archive select" ".close archive" "archive select" ".exit" | "./$PROG" "$DB" 2>&1)"
+  rm "$other"
+  local expected="$PROMPT executed.
+$PROMPT opened $other as archive.
+$PROMPT executed.
+$PROMPT [archived]
+executed.
+$PROMPT [main]
+executed.
+$PROMPT ERROR: $DB is already open.
+$PROMPT ERROR: 'select' is a keyword, pick another alias.
+$PROMPT ERROR: .open --as <alias> <database>.
+$PROMPT 
+$PROMPT opened $other as archive.
+$PROMPT [1, archived, bar]
+executed.
+$PROMPT closed archive.
+$PROMPT ERROR: unkown statement keyword: 'archive select'
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "open_as"
+}
+
+function test_dry_run() {
+  local commands=()
+  for i in $(seq $((LEAF_NODE_CELL_MAX_NUM - 1))); do
+    commands+=("insert $i foo bar")
+  done
+  commands+=(".dryrun on")
+  commands+=("insert $LEAF_NODE_CELL_MAX_NUM foo bar")
+  commands+=(".dryrun off")
+  commands+=("insert $LEAF_NODE_CELL_MAX_NUM foo bar")
+  commands+=(".exit")
+  exec_command "${commands[@]}" > /dev/null # for side effect
+  commands=(
+    "insert 1 foo bar"
+    "insert 100 foo bar"
+    "analyze"
+    ".dryrun"
+    "select count(*)"
+    ".exit"
+  )
+  local got=$(printf "%s\n" "${commands[@]}" | "./$PROG" --dry-run "$DB" 2>&1)
+  local expected="$PROMPT ERROR: key '1' already exist.
+$PROMPT dry run: 1 row would go into leaf page 1, splitting the root into 2 new leaf pages.
+$PROMPT ERROR: analyze writes to the database, turn dry run off first.
+$PROMPT dry run: on.
+$PROMPT [$LEAF_NODE_CELL_MAX_NUM]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "dry_run"
+}
+
+function test_history() {
+  printf "0\tok\tinsert 1 foo bar\n1760486400\terror\tselect x\ncut short\n" > "$RQLITE_HISTORY"
+  local commands=(
+    ".history"
+    "!1"
+    "!9"
+    ".history 1"
+    ".exit"
+  )
+  local got=$(exec_command "${commands[@]}" | sed "s/ 3  [0-9-]* [0-9:]*  ok / 3  now  ok /")
+  got+="$NEW_LINE$(tail -n 1 "$RQLITE_HISTORY" | cut -f 2-)"
+  local expected="$PROMPT     1  1970-01-01 00:00:00  ok     insert 1 foo bar
+    2  2025-10-15 00:00:00  error  select x
+$PROMPT insert 1 foo bar
+executed.
+$PROMPT ERROR: no statement 9 in the history.
+$PROMPT     3  now  ok     insert 1 foo bar
+$PROMPT 
+ok	insert 1 foo bar"
+  assert_and_drop_db "$got" "$expected" "history"
+}
+
+function test_watch() {
+  exec_command "insert 1 foo bar" ".exit" > /dev/null # for side effect
+  local out="watch.out"
+  # the second round runs after another process has added a row
+  printf "%s\n" ".watch 1 --count 2 select count(*)" ".exit" | "./$PROG" "$DB" > "$out" 2>&1 &
+  sleep 0.5
+  exec_command "insert 2 foo bar" ".exit" > /dev/null
+  wait
+  local got=$(cat "$out")
+  got+="$NEW_LINE$(exec_command ".watch x select" ".watch 1 insert 3 foo bar" ".exit")"
+  rm "$out"
+  local expected="$PROMPT every 1s: select count(*)
+[1]
+every 1s: select count(*)
+[2]
+$PROMPT 
+$PROMPT ERROR: .watch <seconds> [--count N] <select statement>.
+$PROMPT ERROR: .watch <seconds> [--count N] <select statement>.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "watch"
+}
+
+function test_profile() {
+  local got=$(exec_command \
+    "insert 1 ab abcd" \
+    "insert 2 abcdef x" \
+    "insert 3 a abcdefghij" \
+    ".profile" \
+    ".profile main" \
+    ".profile nope" \
+    ".exit" | grep -v "^$PROMPT executed.$" | grep -v "^rows: \|^padding bytes: ")
+  local expected="$PROMPT PROFILE:
+name length: min 1, p50 2, p90 6, max 6 of 32
+description length: min 1, p50 4, p90 10, max 10 of 256
+leaf page 1: 3 of $LEAF_NODE_CELL_MAX_NUM cells, 2% of text space used
+$PROMPT PROFILE:
+name length: min 1, p50 2, p90 6, max 6 of 32
+description length: min 1, p50 4, p90 10, max 10 of 256
+leaf page 1: 3 of $LEAF_NODE_CELL_MAX_NUM cells, 2% of text space used
+$PROMPT ERROR: no table named 'nope'.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "profile"
+}
+
+function test_analyze_tree() {
+  local commands=(".analyze_tree")
+  for i in $(seq $LEAF_NODE_CELL_MAX_NUM); do
+    commands+=("insert $((i * 10)) foo bar")
+  done
+  # inserts into the middle split leaves in half
+  for i in $(seq 8); do
+    commands+=("insert $((60 + i)) foo bar")
+  done
+  commands+=(".analyze_tree")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -v "^$PROMPT executed.$" | sed "s/^\($PROMPT \)*//")
+  local expected="ANALYZE TREE:
+depth: 1
+level 1 leaf: pages 1, cells 0 of $LEAF_NODE_CELL_MAX_NUM (0%)
+tree pages: 1, a full rebuild needs 1
+ANALYZE TREE:
+depth: 2
+level 1 internal: pages 1, fan-out 3.0, children 3 of 341 (0%)
+level 2 leaf: pages 3, cells 21 of 39 (53%)
+tree pages: 4, a full rebuild needs 3
+pages .optimize would save: 1"
+  assert_and_drop_db "$got" "$expected" "analyze tree"
+}
+
+function test_select_in() {
+  local commands=()
+  for i in $(seq $((2 * LEAF_NODE_CELL_MAX_NUM))); do
+    commands+=("insert $i foo$i bar")
+  done
+  commands+=("select in ($((2 * LEAF_NODE_CELL_MAX_NUM)), 3, 100, 3, -1) id, name")
+  commands+=("select in ()")
+  commands+=("select in (1) where id = 1")
+  commands+=("explain select in (1, 2, $((2 * LEAF_NODE_CELL_MAX_NUM)))")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -v "^$PROMPT executed.$")
+  local expected="$PROMPT [3, foo3]
+[$((2 * LEAF_NODE_CELL_MAX_NUM)), foo$((2 * LEAF_NODE_CELL_MAX_NUM))]
+executed.
+$PROMPT ERROR: in needs a list of ids, like in (1, 2).
+$PROMPT ERROR: in takes no where or order by.
+$PROMPT QUERY PLAN:
+primary key seeks to leaf pages 3, 2
+pages expected: about 3
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "select_in"
+}
+
+function test_resume_bookmark() {
+  local commands=()
+  for i in $(seq $((2 * LEAF_NODE_CELL_MAX_NUM))); do
+    commands+=("insert $((i * 2)) foo bar")
+  done
+  commands+=("select after 10 limit 3 id")
+  commands+=(".exit")
+  local bookmark=$(exec_command "${commands[@]}" | grep -o "bm[0-9a-f]*")
+  # a new process picks up where the last one stopped
+  local got=$(exec_command \
+    "select resume $bookmark limit 2 id" \
+    "explain select resume $bookmark limit 2" \
+    "insert 17 new bar" \
+    "select resume $bookmark limit 2 id" \
+    "select resume nope" \
+    ".exit" | grep -v "^$PROMPT executed.$")
+  local expected="$PROMPT [18]
+[20]
+bookmark: bm00000003000a0000000000000016
+executed.
+$PROMPT QUERY PLAN:
+resume at leaf page 3 cell 8, no descent
+scan along the leaves for at most 2 rows
+pages expected: about 2
+$PROMPT ERROR: bookmark is stale, the table changed since.
+$PROMPT ERROR: resume needs a bookmark from a select with a limit.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "resume_bookmark"
+}
+
+function test_batch() {
+  local commands=(
+    "insert 1 foo bar"
+    ".batch begin"
+  )
+  # enough rows to split the root before the duplicate is reached
+  for i in $(seq 2 $((2 * LEAF_NODE_CELL_MAX_NUM))); do
+    commands+=("insert $i foo bar")
+  done
+  commands+=("insert 1 dup bar")
+  commands+=(".batch")
+  commands+=(".batch apply")
+  commands+=("select count(*)")
+  commands+=(".tree")
+  commands+=(".batch begin")
+  commands+=("insert 3 foo bar")
+  commands+=("insert 2 foo bar")
+  commands+=(".batch apply")
+  commands+=("select changes(), last_insert_rowid()")
+  commands+=(".batch discard")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -v "^$PROMPT executed.$\|^$PROMPT queued.$")
+  local expected="$PROMPT $PROMPT queued.
+$PROMPT batch: $((2 * LEAF_NODE_CELL_MAX_NUM)) rows queued.
+$PROMPT ERROR: key '1' already exist.
+$PROMPT [1]
+executed.
+$PROMPT TREE:
+- leaf (size 1)
+  - 1
+$PROMPT $PROMPT queued.
+$PROMPT applied 2 rows.
+$PROMPT [2, 2]
+executed.
+$PROMPT ERROR: no batch is open, start one with .batch begin.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "batch"
+}
+
+function test_truncate() {
+  local commands=()
+  for i in $(seq $((3 * LEAF_NODE_CELL_MAX_NUM))); do
+    commands+=("insert $i foo bar")
+  done
+  commands+=("truncate")
+  commands+=(".exit")
+  exec_command "${commands[@]}" > /dev/null
+  # only the header page and the empty root are left
+  local size=$(stat -c %s "$DB")
+  local got=$(exec_command \
+    "select count(*)" \
+    ".tree" \
+    "insert 7 foo bar" \
+    "select" \
+    ".dryrun on" \
+    "truncate" \
+    ".exit" | grep -v "^$PROMPT executed.$")
+  local expected="$PROMPT [0]
+executed.
+$PROMPT TREE:
+- leaf (size 0)
+$PROMPT [7, foo, bar]
+executed.
+$PROMPT $PROMPT ERROR: truncate writes to the database, turn dry run off first.
+$PROMPT "
+  assert_and_drop_db "$size $got" "$((2 * PAGE_SIZE)) $expected" "truncate"
+}
+
+function test_optimize() {
+  local commands=()
+  for i in $(seq $LEAF_NODE_CELL_MAX_NUM); do
+    commands+=("insert $((i * 10)) foo bar")
+  done
+  # inserts into the middle split leaves in half
+  for i in $(seq 8); do
+    commands+=("insert $((60 + i)) foo bar")
+    commands+=("insert $((30 + i)) foo bar")
+  done
+  commands+=("analyze")
+  commands+=(".optimize")
+  commands+=(".analyze_tree")
+  commands+=("select count(*) where id > 60 and id < 70")
+  commands+=(".dbinfo")
+  commands+=(".exit")
+  local got=$(exec_command "${commands[@]}" | grep -v "^$PROMPT executed.$" | grep -v "^key histogram")
+  local n_rows=$((LEAF_NODE_CELL_MAX_NUM + 16))
+  local n_leaves=$(((n_rows + LEAF_NODE_CELL_MAX_NUM - 1) / LEAF_NODE_CELL_MAX_NUM))
+  local expected="$PROMPT rebuilt the tree, $((n_leaves + 2)) pages down from 6.
+$PROMPT ANALYZE TREE:
+depth: 2
+level 1 internal: pages 1, fan-out $n_leaves.0, children $n_leaves of 341 (0%)
+level 2 leaf: pages $n_leaves, cells $n_rows of $((n_leaves * LEAF_NODE_CELL_MAX_NUM)) ($((n_rows * 100 / (n_leaves * LEAF_NODE_CELL_MAX_NUM)))%)
+tree pages: $((n_leaves + 1)), a full rebuild needs $((n_leaves + 1))
+$PROMPT [8]
+executed.
+$PROMPT DBINFO:
+pages: $((n_leaves + 2))
+rows: $n_rows
+leaves: $n_leaves
+leaf fill: $((n_rows * 100 / (n_leaves * LEAF_NODE_CELL_MAX_NUM)))%
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "optimize"
+}
+
+function test_sequence() {
+  exec_command \
+    "create sequence item_id" \
+    "create sequence big start 100" \
+    "insert nextval('item_id') foo bar" \
+    "insert nextval('big') foo bar" \
+    ".exit" > /dev/null
+  # the next values were kept in the header
+  local got=$(exec_command \
+    "insert nextval('item_id') foo bar" \
+    "select last_insert_rowid()" \
+    "create sequence item_id" \
+    "insert nextval('missing') foo bar" \
+    ".dbinfo" \
+    "select id" \
+    ".exit" | grep -v "^$PROMPT executed.$")
+  local expected="$PROMPT [2]
+executed.
+$PROMPT ERROR: sequence 'item_id' already exists.
+$PROMPT ERROR: no sequence named 'missing'.
+$PROMPT DBINFO:
+pages: 2
+rows: 3
+stats: none, run analyze.
+sequence item_id: next 3
+sequence big: next 101
+$PROMPT [1]
+[2]
+[100]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "sequence"
+}
+
+function test_json_extract() {
+  local got=$(exec_command \
+    'insert 1 a {"kind":"book","pages":300}' \
+    'insert 2 b {"kind":"film","mins":90}' \
+    'insert 3 c {"kind":"book","pages":120}' \
+    "select id, json_extract(description, '\$.pages') where json_extract(description, '\$.kind') = 'book'" \
+    "select id where json_extract(description, '\$.pages') > 200" \
+    "select json_extract(description, '\$.pages') where id = 2" \
+    "select json_extract(description, 'pages')" \
+    "insert 4 d plain" \
+    "select json_extract(description, '\$.kind') where id = 4" \
+    ".exit" | grep -v "^$PROMPT executed.$")
+  local expected="$PROMPT [1, 300]
+[3, 120]
+executed.
+$PROMPT [1]
+executed.
+$PROMPT [NULL]
+executed.
+$PROMPT ERROR: json_extract() takes a '\$.key' path, got 'pages'.
+$PROMPT ERROR: json_extract(): expected '{', found 'p'.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "json_extract"
+}
+
+function test_explain_analyze() {
+  local got=$(exec_command \
+    "insert 1 a x" \
+    "insert 2 b y" \
+    "insert 3 c z" \
+    "explain analyze select id where id > 1 order by name desc" \
+    "explain analyze insert 4 d w" \
+    "explain analyze pragma x" \
+    "select count(*)" \
+    ".exit" | grep -v "^$PROMPT executed.$" | sed -E 's/[0-9.]+(ns|µs|ms|s)/T/g')
+  local expected="$PROMPT QUERY PLAN:
+full scan of primary key from leaf page 1
+filter rows by where clause
+merge sort for order by, spilling runs of 256 rows
+pages expected: unknown, run analyze.
+ANALYZE:
+parse: T
+plan: T
+execute: T
+  pager: 0 pages read in 0 calls, 2 cache hits, T in i/o
+  scan: 3 rows examined
+  sort: T
+rows returned: 2
+total: T
+$PROMPT QUERY PLAN:
+append to rightmost leaf page 1
+pages expected: 1
+ANALYZE:
+parse: T
+plan: T
+execute: T
+  pager: 0 pages read in 0 calls, 3 cache hits, T in i/o
+  scan: 0 rows examined
+rows written: 1
+total: T
+$PROMPT ERROR: cannot explain analyze 'pragma'.
+$PROMPT [4]
+executed.
+$PROMPT "
+  assert_and_drop_db "$got" "$expected" "explain analyze"
+}
+
 function test_print_constants() {
   local commands=(
     ".constants"
@@ -275,7 +1684,7 @@ function test_print_tree() {
   done
   expected+="$PROMPT TREE:
 - internal (size 1)
-  - leaf (size 7)
+  - leaf (size 13)
     - 1
     - 2
     - 3
@@ -283,14 +1692,14 @@ function test_print_tree() {
     - 5
     - 6
     - 7
-  - key 7
-  - leaf (size 7)
     - 8
     - 9
     - 10
     - 11
     - 12
     - 13
+  - key 13
+  - leaf (size 1)
     - 14
 $PROMPT "
   assert_and_drop_db "$got" "$expected" "print_tree"
@@ -333,21 +1742,85 @@ function test_select_all_nodes() {
   assert_and_drop_db "$got" "$expected" "select_all_nodes"
 }
 
+function test_bench_seq() {
+  local got=$("./$PROG" bench "$DB" --rows $((LEAF_NODE_CELL_MAX_NUM + 1)) --mode seq | grep -E "^(rows|pages written|write calls|file size):")
+  local expected="rows: $((LEAF_NODE_CELL_MAX_NUM + 1))
+pages written: 4
+write calls: 1
+file size: $((4 * PAGE_SIZE)) bytes"
+  assert_and_drop_db "$got" "$expected" "bench_seq"
+}
+
 setup
 test_insert_less_args
 test_insert_not_num_id
 test_insert_duplicated_id
+test_insert_duplicated_id_after_split
 test_insert_one
 test_insert_pass_max
+test_insert_split_non_root_leaf
 test_insert_out_of_order
 test_negative_id
 test_name_and_description_max_len
+test_multi_byte_name
 test_name_len_pass_max
 test_description_pass_max
+test_import_sorted
+test_import_unsorted
+test_import_csv_options
 test_persistence
+test_persistence_across_sessions
+test_stats
+test_stats_readahead
+test_analyze
+test_explain
+test_corrupt_node_kind
+test_corrupt_leaf_cycle
+test_not_a_database
+test_disk_full
+test_close_flush_error
+test_migrate
+test_migrate_version_0
+test_trailing_tokens
+test_stdout_and_exit_codes
+test_recover
+test_export_sqlite
+test_import_sqlite
+test_jsonl
+test_diff
+test_dump_restore
+test_snapshot_verify
+test_stats_subcommand
+test_select_expressions
+test_last_insert_rowid
+test_timeout
+test_slow_log
+test_pragma
+test_order_by
+test_split_sequential_edges
+test_exists
+test_count
+test_sample
+test_first_last
+test_after_limit
+test_open_as
+test_dry_run
+test_history
+test_watch
+test_profile
+test_analyze_tree
+test_select_in
+test_resume_bookmark
+test_batch
+test_truncate
+test_optimize
+test_sequence
+test_json_extract
+test_explain_analyze
 test_print_constants
 test_print_tree
 test_search_in_internal_node
 test_select_all_nodes
+test_bench_seq
 summary_test
 teardown